@@ -1,28 +1,698 @@
-/*
-const emulatedOpcodes = {
-    "ret": "mov @sp+, pc",
-    "clrc": "bic #1, sr",
-    "setc": "bis #1, sr",
-    "clrz": "bic #2, sr",
-    "setz": "bis #2, sr",
-    "clrn": "bic #4, sr",
-    "setn": "bis #4, sr",
-    "dint": "bic #8, sr",
-    "eint": "bis #8, sr",
-    "nop": "mov r3, r3",  # Any register would do the same
-    "br": "mov {reg}, pc",
-    "pop": "mov @sp+, {reg}",
-    "rla": "add {reg}, {reg}",
-    "rlc": "addc {reg}, {reg}",
-    "inv": "xor #0xffff, {reg}",
-    "clr": "mov #0, {reg}",
-    "tst": "cmp #0, {reg}",
-    "dec": "sub #1, {reg}",
-    "decd": "sub #2, {reg}",
-    "inc": "add #1, {reg}",
-    "incd": "add #2, {reg}",
-    "adc": "addc #0, {reg}",
-    "dadc": "dadd #0, {reg}",
-    "sbc": "subc #0, {reg}",
-};
-*/
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+use crate::utils::data_address::{self, AddresingMode, AsmInstruction, DataMode, Register};
+use crate::utils::jumps::{JumpInstruction, JumpOp};
+use crate::utils::one_op::{OneOp, OneOpInstruction};
+use crate::utils::two_op::{EmulatedInstruction, EmulatedOp, TwoOp, TwoOpInstruction};
+use crate::utils::Instruction;
+
+#[derive(Debug)]
+pub enum AssemblyError {
+    Io(io::Error),
+    Source { line: usize, message: String },
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "unable to read the source: {}", error),
+            Self::Source { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for AssemblyError {}
+
+impl From<io::Error> for AssemblyError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Literal(u16),
+    Label(String),
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Direct(Register),
+    Indexed(Value, Register),
+    Indirect(Register),
+    Autoincrement(Register),
+    Absolute(Value),
+    Symbolic(Value),
+    Immediate(Value),
+}
+
+#[derive(Debug)]
+enum JumpTarget {
+    Address(Value),
+    // Byte offset from the address of the jump itself, written as `$+n`
+    Relative(i32),
+}
+
+#[derive(Debug)]
+enum Statement {
+    TwoOp {
+        operation: TwoOp,
+        mode: DataMode,
+        source: Operand,
+        destination: Operand,
+    },
+    OneOp {
+        operation: OneOp,
+        mode: Option<DataMode>,
+        data: Operand,
+    },
+    Jump {
+        operation: JumpOp,
+        target: JumpTarget,
+    },
+    Emulated {
+        operation: EmulatedOp,
+        mode: Option<DataMode>,
+        data: Option<Operand>,
+    },
+    Words(Vec<Value>),
+}
+
+#[derive(Debug)]
+struct SourceLine {
+    number: usize,
+    address: u16,
+    statement: Statement,
+    constant_generator: bool,
+}
+
+pub fn assemble<T: BufRead>(
+    reader: &mut T,
+    pc_base: u16,
+    constant_generator: bool,
+) -> Result<Vec<u16>, AssemblyError> {
+    let mut labels: HashMap<String, u16> = HashMap::new();
+    let mut source_lines: Vec<SourceLine> = Vec::new();
+    let mut address = u32::from(pc_base);
+
+    // First pass: parse the statements and give an address to every label
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let number = index + 1;
+        let source_error = |message: String| AssemblyError::Source {
+            line: number,
+            message,
+        };
+
+        let mut text = line.split(';').next().unwrap_or_default().trim();
+        while let Some((label, rest)) = split_label(text) {
+            if labels.insert(label.to_string(), address as u16).is_some() {
+                return Err(source_error(format!("label \"{}\" defined twice", label)));
+            }
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let statement = parse_statement(text).map_err(source_error)?;
+        let use_constants = constant_generator && allows_constants(&statement);
+        let size = statement_words(&statement, use_constants);
+        source_lines.push(SourceLine {
+            number,
+            address: address as u16,
+            statement,
+            constant_generator: use_constants,
+        });
+
+        address += 2 * size as u32;
+        if address > 0x10000 {
+            return Err(source_error(
+                "the program does not fit in the address space".to_string(),
+            ));
+        }
+    }
+
+    // Second pass: resolve the labels and encode every statement
+    let mut words: Vec<u16> = Vec::new();
+    for line in source_lines {
+        let encoded =
+            encode_statement(&line, &labels).map_err(|message| AssemblyError::Source {
+                line: line.number,
+                message,
+            })?;
+        words.extend(encoded);
+    }
+
+    Ok(words)
+}
+
+pub fn to_hex_string(words: &[u16]) -> String {
+    words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' || first == '.' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        }
+        _ => false,
+    }
+}
+
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (label, rest) = text.split_once(':')?;
+    let label = label.trim();
+    if is_identifier(label) {
+        Some((label, rest))
+    } else {
+        None
+    }
+}
+
+fn parse_number(text: &str) -> Result<i32, String> {
+    let (negative, digits) = if let Some(digits) = text.strip_prefix('-') {
+        (true, digits.trim())
+    } else {
+        (false, text.strip_prefix('+').unwrap_or(text).trim())
+    };
+
+    let lowercase = digits.to_lowercase();
+    let magnitude = if let Some(hex) = lowercase.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16)
+    } else {
+        lowercase.parse::<i32>()
+    }
+    .map_err(|_| format!("invalid number \"{}\"", text))?;
+
+    Ok(if negative { -magnitude } else { magnitude })
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    let text = text.trim();
+    if is_identifier(text) {
+        return Ok(Value::Label(text.to_string()));
+    }
+
+    let number = parse_number(text)?;
+    if !(-0x8000..=0xffff).contains(&number) {
+        return Err(format!("the value \"{}\" does not fit in 16 bits", text));
+    }
+    Ok(Value::Literal(number as u16))
+}
+
+fn parse_register(text: &str) -> Result<Register, String> {
+    Register::from_str(text.trim()).map_err(|_| format!("invalid register \"{}\"", text))
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    let text = text.trim();
+    if let Some(value) = text.strip_prefix('#') {
+        return Ok(Operand::Immediate(parse_value(value)?));
+    }
+    if let Some(value) = text.strip_prefix('&') {
+        return Ok(Operand::Absolute(parse_value(value)?));
+    }
+    if let Some(register) = text.strip_prefix('@') {
+        return if let Some(register) = register.strip_suffix('+') {
+            Ok(Operand::Autoincrement(parse_register(register)?))
+        } else {
+            Ok(Operand::Indirect(parse_register(register)?))
+        };
+    }
+    if let Some(indexed) = text.strip_suffix(')') {
+        let (offset, register) = indexed
+            .split_once('(')
+            .ok_or_else(|| format!("invalid operand \"{}\"", text))?;
+        return Ok(Operand::Indexed(
+            parse_value(offset)?,
+            parse_register(register)?,
+        ));
+    }
+    if let Ok(register) = Register::from_str(text) {
+        return Ok(Operand::Direct(register));
+    }
+
+    Ok(Operand::Symbolic(parse_value(text)?))
+}
+
+fn split_operands(text: &str) -> Vec<&str> {
+    if text.is_empty() {
+        Vec::new()
+    } else if text.contains(',') {
+        text.split(',').map(str::trim).collect()
+    } else {
+        text.split_whitespace().collect()
+    }
+}
+
+fn parse_jump_target(text: &str) -> Result<JumpTarget, String> {
+    if let Some(offset) = text.strip_prefix('$') {
+        let offset = offset.trim();
+        if offset.is_empty() {
+            return Ok(JumpTarget::Relative(0));
+        }
+        if !offset.starts_with('+') && !offset.starts_with('-') {
+            return Err(format!("invalid jump target \"{}\"", text));
+        }
+        return Ok(JumpTarget::Relative(parse_number(offset)?));
+    }
+    Ok(JumpTarget::Address(parse_value(text)?))
+}
+
+fn parse_statement(text: &str) -> Result<Statement, String> {
+    let (mnemonic, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let mnemonic = mnemonic.to_lowercase();
+    let operands = split_operands(rest.trim());
+
+    if mnemonic == ".word" {
+        if operands.is_empty() {
+            return Err("\".word\" requires at least one value".to_string());
+        }
+        let values = operands
+            .into_iter()
+            .map(parse_value)
+            .collect::<Result<Vec<Value>, String>>()?;
+        return Ok(Statement::Words(values));
+    }
+
+    let (name, mode) = if let Some(name) = mnemonic.strip_suffix(".b") {
+        (name, Some(DataMode::Byte))
+    } else if let Some(name) = mnemonic.strip_suffix(".w") {
+        (name, Some(DataMode::Word))
+    } else {
+        (mnemonic.as_str(), None)
+    };
+
+    if let Ok(operation) = TwoOp::from_str(name) {
+        if operands.len() != 2 {
+            return Err(format!("\"{}\" requires two operands", name));
+        }
+        let destination = parse_operand(operands[1])?;
+        if !matches!(
+            destination,
+            Operand::Direct(_)
+                | Operand::Indexed(_, _)
+                | Operand::Absolute(_)
+                | Operand::Symbolic(_)
+        ) {
+            return Err(format!("invalid destination operand \"{}\"", operands[1]));
+        }
+        return Ok(Statement::TwoOp {
+            operation,
+            mode: mode.unwrap_or(DataMode::Word),
+            source: parse_operand(operands[0])?,
+            destination,
+        });
+    }
+
+    if let Ok(operation) = OneOp::from_str(name) {
+        if operation == OneOp::Reti {
+            if !operands.is_empty() {
+                return Err("\"reti\" does not take operands".to_string());
+            }
+            return Ok(Statement::OneOp {
+                operation,
+                mode,
+                data: Operand::Direct(Register::Pc),
+            });
+        }
+        if operands.len() != 1 {
+            return Err(format!("\"{}\" requires one operand", name));
+        }
+        return Ok(Statement::OneOp {
+            operation,
+            mode,
+            data: parse_operand(operands[0])?,
+        });
+    }
+
+    if let Ok(operation) = EmulatedOp::from_str(name) {
+        if !operation.takes_operand() {
+            if !operands.is_empty() {
+                return Err(format!("\"{}\" does not take operands", name));
+            }
+            return Ok(Statement::Emulated {
+                operation,
+                mode,
+                data: None,
+            });
+        }
+        if operands.len() != 1 {
+            return Err(format!("\"{}\" requires one operand", name));
+        }
+        return Ok(Statement::Emulated {
+            operation,
+            mode,
+            data: Some(parse_operand(operands[0])?),
+        });
+    }
+
+    if let Ok(operation) = JumpOp::from_str(name) {
+        if mode.is_some() {
+            return Err("jumps do not have a data mode".to_string());
+        }
+        if operands.len() != 1 {
+            return Err(format!("\"{}\" requires a target", name));
+        }
+        return Ok(Statement::Jump {
+            operation,
+            target: parse_jump_target(operands[0])?,
+        });
+    }
+
+    Err(format!("unknown mnemonic \"{}\"", mnemonic))
+}
+
+// Labels are only known in the second pass, so their immediates always take the long form
+fn allows_constants(statement: &Statement) -> bool {
+    match statement {
+        Statement::TwoOp { source, .. } => !matches!(source, Operand::Immediate(Value::Label(_))),
+        Statement::OneOp {
+            operation, data, ..
+        } => match data {
+            Operand::Immediate(Value::Label(_)) => false,
+            Operand::Immediate(Value::Literal(value)) => operation.supports_constant(*value),
+            _ => true,
+        },
+        Statement::Emulated {
+            data: Some(Operand::Immediate(Value::Label(_))),
+            ..
+        } => false,
+        _ => true,
+    }
+}
+
+fn source_words(operand: &Operand, constant_generator: bool) -> u16 {
+    match operand {
+        Operand::Direct(_) | Operand::Indirect(_) | Operand::Autoincrement(_) => 0,
+        Operand::Immediate(Value::Literal(value))
+            if constant_generator && data_address::constant_generator(*value).is_some() =>
+        {
+            0
+        }
+        _ => 1,
+    }
+}
+
+fn destination_words(operand: &Operand) -> u16 {
+    match operand {
+        Operand::Direct(_) => 0,
+        _ => 1,
+    }
+}
+
+fn statement_words(statement: &Statement, constant_generator: bool) -> u16 {
+    match statement {
+        Statement::TwoOp {
+            source,
+            destination,
+            ..
+        } => 1 + source_words(source, constant_generator) + destination_words(destination),
+        Statement::OneOp { data, .. } => 1 + source_words(data, constant_generator),
+        Statement::Jump { .. } => 1,
+        // Implied sources are registers or constants, only the user provided operand can grow
+        Statement::Emulated {
+            operation, data, ..
+        } => match (operation, data) {
+            (_, None) => 1,
+            (EmulatedOp::Br, Some(data)) => 1 + source_words(data, constant_generator),
+            (EmulatedOp::Rla | EmulatedOp::Rlc, Some(data)) => {
+                1 + source_words(data, constant_generator) + destination_words(data)
+            }
+            (_, Some(data)) => 1 + destination_words(data),
+        },
+        Statement::Words(values) => values.len() as u16,
+    }
+}
+
+fn resolve_value(value: &Value, labels: &HashMap<String, u16>) -> Result<u16, String> {
+    match value {
+        Value::Literal(literal) => Ok(*literal),
+        Value::Label(label) => labels
+            .get(label)
+            .copied()
+            .ok_or_else(|| format!("undefined label \"{}\"", label)),
+    }
+}
+
+fn resolve_operand(
+    operand: &Operand,
+    labels: &HashMap<String, u16>,
+    extension_address: u16,
+) -> Result<AddresingMode, String> {
+    Ok(match operand {
+        Operand::Direct(register) => AddresingMode::Direct(*register),
+        Operand::Indexed(offset, register) => {
+            AddresingMode::Indexed((resolve_value(offset, labels)?, *register))
+        }
+        Operand::Indirect(register) => AddresingMode::Indirect(*register),
+        Operand::Autoincrement(register) => AddresingMode::Autoincrement(*register),
+        Operand::Absolute(address) => AddresingMode::Absolute(resolve_value(address, labels)?),
+        Operand::Symbolic(address) => {
+            AddresingMode::Symbolic(resolve_value(address, labels)?.wrapping_sub(extension_address))
+        }
+        Operand::Immediate(value) => AddresingMode::Immediate(resolve_value(value, labels)?),
+    })
+}
+
+fn encode_statement(line: &SourceLine, labels: &HashMap<String, u16>) -> Result<Vec<u16>, String> {
+    let first_extension = line.address.wrapping_add(2);
+    let instruction = match &line.statement {
+        Statement::TwoOp {
+            operation,
+            mode,
+            source,
+            destination,
+        } => {
+            let second_extension =
+                first_extension.wrapping_add(2 * source_words(source, line.constant_generator));
+            Instruction::TwoOp(
+                TwoOpInstruction::build(
+                    *operation,
+                    resolve_operand(source, labels, first_extension)?,
+                    resolve_operand(destination, labels, second_extension)?,
+                    *mode,
+                )?
+                .with_constant_generator(line.constant_generator),
+            )
+        }
+        Statement::OneOp {
+            operation,
+            mode,
+            data,
+        } => Instruction::OneOp(
+            OneOpInstruction::build(
+                *operation,
+                resolve_operand(data, labels, first_extension)?,
+                *mode,
+            )?
+            .with_constant_generator(line.constant_generator),
+        ),
+        Statement::Jump { operation, target } => {
+            let target = match target {
+                JumpTarget::Address(value) => i32::from(resolve_value(value, labels)?),
+                JumpTarget::Relative(offset) => i32::from(line.address) + offset,
+            };
+            let distance = target - (i32::from(line.address) + 2);
+            if distance % 2 != 0 {
+                return Err("jump targets must be word aligned".to_string());
+            }
+            let offset = i16::try_from(distance / 2)
+                .map_err(|_| "the jump target is out of range".to_string())?;
+            Instruction::Jump(
+                JumpInstruction::build(*operation, offset)
+                    .map_err(|_| "the jump target is out of range".to_string())?,
+            )
+        }
+        Statement::Emulated {
+            operation,
+            mode,
+            data,
+        } => {
+            let data = data
+                .as_ref()
+                .map(|operand| resolve_operand(operand, labels, first_extension))
+                .transpose()?;
+            let expanded =
+                TwoOpInstruction::from(EmulatedInstruction::build(*operation, data, *mode)?);
+            // Only the source of a branch comes from the user, the other implied constants are
+            // always generated
+            if *operation == EmulatedOp::Br {
+                Instruction::TwoOp(expanded.with_constant_generator(line.constant_generator))
+            } else {
+                Instruction::TwoOp(expanded)
+            }
+        }
+        Statement::Words(values) => {
+            return values
+                .iter()
+                .map(|value| resolve_value(value, labels))
+                .collect();
+        }
+    };
+
+    Ok(instruction.encode())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::data_address::effective_address;
+    use crate::utils::decode_at;
+
+    const BASE: u16 = 0xc000;
+
+    fn assemble_source(source: &str, constant_generator: bool) -> Vec<u16> {
+        assemble(&mut source.as_bytes(), BASE, constant_generator).unwrap()
+    }
+
+    // Assembles a single instruction and decodes it back, checking that it spans all the words
+    fn round_trip(source: &str) -> TwoOpInstruction {
+        let words = assemble_source(source, true);
+        match decode_at(&words, BASE).unwrap() {
+            (Instruction::TwoOp(instruction), step) if step == words.len() => instruction,
+            decoded => panic!("{} decoded as {:?}", source, decoded),
+        }
+    }
+
+    #[test]
+    fn addressing_modes_round_trip() {
+        let cases = [
+            ("mov r5, r6", AddresingMode::Direct(Register::R5)),
+            ("mov 4(r5), r6", AddresingMode::Indexed((4, Register::R5))),
+            ("mov @r5, r6", AddresingMode::Indirect(Register::R5)),
+            ("mov @r5+, r6", AddresingMode::Autoincrement(Register::R5)),
+            ("mov &0x200, r6", AddresingMode::Absolute(0x200)),
+            // Relative to the extension word, right after the first one
+            (
+                "mov 0xc010, r6",
+                AddresingMode::Symbolic(0xc010 - (BASE + 2)),
+            ),
+            ("mov #0x1234, r6", AddresingMode::Immediate(0x1234)),
+        ];
+        for (source, mode) in cases {
+            let instruction = round_trip(source);
+            assert_eq!(instruction.source(), mode, "{}", source);
+            assert_eq!(
+                instruction.destination(),
+                AddresingMode::Direct(Register::R6),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn destination_modes_round_trip() {
+        let cases = [
+            ("mov r5, 2(r6)", AddresingMode::Indexed((2, Register::R6))),
+            ("mov r5, &0x0120", AddresingMode::Absolute(0x0120)),
+            (
+                "mov r5, 0xc010",
+                AddresingMode::Symbolic(0xc010 - (BASE + 2)),
+            ),
+        ];
+        for (source, mode) in cases {
+            assert_eq!(round_trip(source).destination(), mode, "{}", source);
+        }
+    }
+
+    #[test]
+    fn symbolic_labels_resolve_to_their_address() {
+        let words = assemble_source("mov data, r6\nret\ndata: .word 0x1234", true);
+        let (instruction, _) = decode_at(&words, BASE).unwrap();
+        let (mode, extension_address) = instruction.operands_at(BASE)[0];
+        assert_eq!(effective_address(mode, extension_address), Some(BASE + 6));
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        match assemble(&mut "nop\nmov r5".as_bytes(), BASE, true) {
+            Err(AssemblyError::Source { line, .. }) => assert_eq!(line, 2),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn emulated_instructions_expand() {
+        let cases: [(&str, &[u16]); 13] = [
+            ("ret", &[0x4130]),
+            ("nop", &[0x4303]),
+            ("pop r5", &[0x4135]),
+            ("br #0x1234", &[0x4030, 0x1234]),
+            ("clr r5", &[0x4305]),
+            ("inc r5", &[0x5315]),
+            ("decd r5", &[0x8325]),
+            ("tst r5", &[0x9305]),
+            ("rla r5", &[0x5505]),
+            ("inv.b r5", &[0xe375]),
+            ("clrc", &[0xc312]),
+            ("dint", &[0xc232]),
+            ("eint", &[0xd232]),
+        ];
+        for (source, words) in cases {
+            assert_eq!(assemble_source(source, true), words.to_vec(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn emulated_instructions_round_trip() {
+        for source in [
+            "ret", "nop", "pop R5", "clr R5", "inc R5", "tst R5", "rla R5",
+        ] {
+            let words = assemble_source(source, true);
+            let (instruction, _) = decode_at(&words, BASE).unwrap();
+            assert_eq!(instruction.to_string(), source);
+        }
+    }
+
+    #[test]
+    fn constants_come_from_the_generator() {
+        let cases = [
+            ("add #0, r5", 0x5305, 0),
+            ("add #1, r5", 0x5315, 1),
+            ("add #2, r5", 0x5325, 2),
+            ("add #4, r5", 0x5225, 4),
+            ("add #8, r5", 0x5235, 8),
+            ("add #-1, r5", 0x5335, 0xffff),
+        ];
+        for (source, word, value) in cases {
+            assert_eq!(assemble_source(source, true), vec![word], "{}", source);
+            assert_eq!(
+                round_trip(source).source(),
+                AddresingMode::Immediate(value),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn constant_generator_can_be_disabled() {
+        assert_eq!(assemble_source("add #1, r5", false), vec![0x5035, 0x0001]);
+        assert_eq!(assemble_source("add #8, r5", false), vec![0x5035, 0x0008]);
+    }
+
+    #[test]
+    fn emulated_constants_stay_generated() {
+        assert_eq!(assemble_source("inc r5", false), vec![0x5315]);
+        assert_eq!(assemble_source("br #2", false), vec![0x4030, 0x0002]);
+    }
+
+    #[test]
+    fn push_avoids_the_status_register_constants() {
+        assert_eq!(assemble_source("push #4", true), vec![0x1230, 0x0004]);
+        assert_eq!(assemble_source("push #8", true), vec![0x1230, 0x0008]);
+        assert_eq!(assemble_source("push #2", true), vec![0x1223]);
+    }
+}
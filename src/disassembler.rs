@@ -1,62 +1,960 @@
+use indicatif::ProgressBar;
 use itertools::Itertools;
-use std::io::{self, BufRead};
-use std::ops::{Shl, Shr};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, BufRead, Write};
+use std::ops::Shl;
 
-use crate::utils::{jumps, one_op, two_op};
+use crate::image::{MemoryImage, Segment};
+use crate::utils::data_address::{effective_address, AddresingMode, DataMode, Register};
+use crate::utils::device::Device;
+use crate::utils::jumps::JumpOp;
+use crate::utils::one_op::OneOp;
+use crate::utils::peripherals::RegisterMap;
+use crate::utils::two_op::{EmulatedOp, TwoOp};
+use crate::utils::{decode_at, DecodeError, Instruction};
 
-pub fn disassemble<T: BufRead>(
-    reader: &mut T,
-    pc_base: u16,
-    quiet: bool,
-) -> io::Result<Vec<String>> {
+#[derive(Debug, Clone, Default)]
+pub struct DisassemblyOptions {
+    // Load address of raw binaries, where their code starts
+    pub pc_base: u16,
+    // Suppresses warnings and progress reporting on stderr, the listing is always written
+    pub quiet: bool,
+    pub pad: bool,
+    pub layout: ListingLayout,
+    // Only list as code what is reachable from the entry points, the rest is listed as data
+    pub recursive: bool,
+    pub entry_points: Vec<u16>,
+    // Leave the interrupt vector table out of both the listing and the code discovery
+    pub ignore_ivt: bool,
+    // Names given to absolute operands
+    pub registers: RegisterMap,
+    // Memory map and vector names of the target part
+    pub device: Option<Device>,
+    pub stack: Option<StackRegion>,
+    // Names known from the input file, used instead of the generated labels
+    pub symbols: BTreeMap<u16, String>,
+}
+
+// The stack grows downwards from `begin`, the initial stack pointer, to `end`, its lowest address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackRegion {
+    begin: u16,
+    end: u16,
+}
+
+impl StackRegion {
+    // Exactly one of `end` and `size` completes the region
+    pub fn new(begin: u16, end: Option<u16>, size: Option<u16>) -> Result<Self, &'static str> {
+        let end = match (end, size) {
+            (Some(end), None) => end,
+            (None, Some(size)) => begin
+                .checked_sub(size)
+                .ok_or("the stack size is larger than its beginning address")?,
+            (Some(_), Some(_)) => return Err("only one of the stack end and size can be given"),
+            (None, None) => return Err("either the stack end or its size is required"),
+        };
+        if end >= begin {
+            return Err("the stack grows downwards, its end must be below its beginning");
+        }
+        Ok(StackRegion { begin, end })
+    }
+
+    pub fn begin(&self) -> u16 {
+        self.begin
+    }
+
+    pub fn end(&self) -> u16 {
+        self.end
+    }
+
+    pub fn size(&self) -> u16 {
+        self.begin - self.end
+    }
+
+    // The word at `begin` is never written, a push decrements SP before storing
+    pub fn contains(&self, address: u16) -> bool {
+        (self.end..self.begin).contains(&address)
+    }
+}
+
+// Columns printed before the instruction text of every listing line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListingLayout {
+    pub address: bool,
+    pub raw_bytes: bool,
+}
+
+impl Default for ListingLayout {
+    fn default() -> Self {
+        ListingLayout {
+            address: true,
+            raw_bytes: true,
+        }
+    }
+}
+
+// An instruction spans at most three words
+const RAW_COLUMN_WIDTH: usize = 3 * 2 * 3;
+
+pub const INTERRUPT_VECTORS: u16 = 0xffe0;
+const RESET_VECTOR: u16 = 0xfffe;
+const NMI_VECTOR: u16 = 0xfffc;
+// Value of an erased flash word, used for vectors that are not programmed
+const UNUSED_VECTOR: u16 = 0xffff;
+
+#[derive(Debug)]
+struct ListingLine {
+    address: u16,
+    bytes: Vec<u8>,
+    instruction: Option<Instruction>,
+    // Handler address, for programmed interrupt vectors
+    vector: Option<u16>,
+    text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Location,
+    Subroutine,
+}
+
+impl LabelKind {
+    fn name(self, address: u16) -> String {
+        match self {
+            LabelKind::Location => format!("loc_{:04x}", address),
+            LabelKind::Subroutine => format!("sub_{:04x}", address),
+        }
+    }
+}
+
+// Word aligned part of a segment, listed as a whole as code, data or interrupt vectors
+#[derive(Debug)]
+struct WordRun {
+    start: u16,
+    words: Vec<u16>,
+    executable: bool,
+    vectors: bool,
+}
+
+impl WordRun {
+    fn address(&self, index: usize) -> u16 {
+        self.start.wrapping_add(2 * index as u16)
+    }
+
+    // Index of the word located at `address`, if the run contains it
+    fn index(&self, address: u16) -> Option<usize> {
+        let offset = address.wrapping_sub(self.start);
+        let index = usize::from(offset / 2);
+        (offset.is_multiple_of(2) && index < self.words.len()).then_some(index)
+    }
+}
+
+// Disassembles a raw binary loaded at the base pointer, which is also its first entry point
+pub fn disassemble<R: BufRead, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    options: &DisassemblyOptions,
+) -> io::Result<()> {
     let mut raw_data: Vec<u8> = Vec::new();
     reader.read_to_end(&mut raw_data)?;
+    if usize::from(options.pc_base) + raw_data.len() > 0x10000 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the input goes past the end of the address space",
+        ));
+    }
+    let mut options = options.clone();
+    options.entry_points.push(options.pc_base);
+    disassemble_image(
+        &MemoryImage::from_bytes(options.pc_base, raw_data),
+        writer,
+        &options,
+    )
+}
+
+// Lists the segments of `image` one after the other, leaving out the gaps between them.
+// Branches and vectors are followed across segments.
+pub fn disassemble_image<W: Write>(
+    image: &MemoryImage,
+    writer: &mut W,
+    options: &DisassemblyOptions,
+) -> io::Result<()> {
+    let mut runs: Vec<WordRun> = Vec::new();
+    let mut listing: Vec<ListingLine> = Vec::new();
+    for segment in image.segments() {
+        let (segment_runs, stray_bytes) = word_runs(segment, options);
+        runs.extend(segment_runs);
+        listing.extend(stray_bytes);
+    }
+
+    let progress = if options.quiet {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(runs.iter().map(|run| run.words.len() as u64).sum())
+    };
+
+    let vectors = runs
+        .iter()
+        .filter(|run| run.vectors)
+        .flat_map(|run| interrupt_vectors(&run.words, run.start))
+        .collect_vec();
+    let code_starts = if options.recursive {
+        Some(reachable_code(&runs, &vectors, options))
+    } else {
+        None
+    };
 
-    let word_data: Vec<u16> = raw_data
+    for run in runs.iter() {
+        let mut curr_word: usize = 0;
+        while curr_word < run.words.len() {
+            let address = run.address(curr_word);
+            if run.vectors {
+                listing.push(vector_line(
+                    address,
+                    run.words[curr_word],
+                    options.device.as_ref(),
+                ));
+                progress.inc(1);
+                curr_word += 1;
+                continue;
+            }
+
+            // Instructions never run past the end of their run
+            let (step, instruction, decoded_op) = match &code_starts {
+                _ if !run.executable => (1, None, format!(".word {:#06x}", run.words[curr_word])),
+                Some(starts) if !starts.contains(&address) => {
+                    (1, None, format!(".word {:#06x}", run.words[curr_word]))
+                }
+                _ => disassemble_op(&run.words[curr_word..], address, options),
+            };
+
+            listing.push(ListingLine {
+                address,
+                bytes: run.words[curr_word..curr_word + step]
+                    .iter()
+                    .flat_map(|word| word.to_le_bytes())
+                    .collect(),
+                instruction,
+                vector: None,
+                text: decoded_op,
+            });
+            progress.inc(step as u64);
+
+            curr_word += step;
+        }
+    }
+    progress.finish_and_clear();
+    listing.sort_by_key(|line| line.address);
+
+    // Second pass, name every branch target that starts a line and refer to it by name
+    let labels = collect_labels(&listing, &options.symbols);
+    for line in listing.iter_mut() {
+        if let Some(text) = line
+            .instruction
+            .and_then(|instruction| labelled_text(&instruction, line.address, &labels))
+        {
+            line.text = text;
+        }
+        if let Some((handler, label)) = line
+            .vector
+            .and_then(|handler| Some((handler, labels.get(&handler)?)))
+        {
+            line.text = vector_text(line.address, label, handler, options.device.as_ref());
+        }
+    }
+
+    write_header(writer, options)?;
+    let mut next_address: Option<u32> = None;
+    for line in listing.iter() {
+        // Gaps in the image are marked by an empty line
+        if next_address.is_some_and(|next| next != u32::from(line.address)) {
+            writeln!(writer)?;
+        }
+        if let Some(label) = labels.get(&line.address) {
+            writeln!(writer, "{}:", label)?;
+        }
+        write_line(writer, line, &options.layout)?;
+        next_address = Some(u32::from(line.address) + line.bytes.len() as u32);
+    }
+    writer.flush()
+}
+
+fn byte_line(address: u16, byte: u8) -> ListingLine {
+    ListingLine {
+        address,
+        bytes: vec![byte],
+        instruction: None,
+        vector: None,
+        text: format!(".byte {:#04x}", byte),
+    }
+}
+
+// Splits a segment into its code and vector table runs. Words are built from byte pairs, so a
+// byte before an odd start address is listed as data, and an odd trailing byte is either padded
+// or listed as data.
+fn word_runs(segment: &Segment, options: &DisassemblyOptions) -> (Vec<WordRun>, Vec<ListingLine>) {
+    let mut stray_bytes: Vec<ListingLine> = Vec::new();
+    let mut start = segment.start;
+    let mut raw_data: Vec<u8> = segment.data.clone();
+
+    if !start.is_multiple_of(2) {
+        if !options.quiet {
+            eprintln!(
+                "Warning: the data at {:#06x} starts at an odd address, its first byte is listed as data",
+                start
+            );
+        }
+        stray_bytes.push(byte_line(start, raw_data.remove(0)));
+        start = start.wrapping_add(1);
+    }
+    if !raw_data.len().is_multiple_of(2) {
+        if !options.quiet {
+            eprintln!(
+                "Warning: the data at {:#06x} is {} bytes long and is not word aligned{}",
+                start,
+                raw_data.len(),
+                if options.pad {
+                    ", padding the last byte with 0x00"
+                } else {
+                    ", the last byte is listed as data"
+                }
+            );
+        }
+        if options.pad {
+            raw_data.push(0);
+        } else if let Some(byte) = raw_data.pop() {
+            stray_bytes.push(byte_line(start.wrapping_add(raw_data.len() as u16), byte));
+        }
+    }
+
+    let mut words: Vec<u16> = raw_data
         .into_iter()
         .tuples()
         .map(|(low, high)| u16::from(high).shl(8) + u16::from(low))
         .collect_vec();
 
-    let mut first_pass_decoded: Vec<String> = Vec::new();
-    let mut curr_word: usize = 0;
-    while curr_word < word_data.len() {
-        let (step, decoded_op) =
-            disassemble_op(&word_data[curr_word..(curr_word + 3).min(word_data.len())]);
-        for s in 0..3 {
-            if s < step {
-                print!("{:#06x} ", word_data[curr_word + s]);
+    // Instructions never run into the vector table
+    let code_length = words
+        .len()
+        .min(usize::from(INTERRUPT_VECTORS.saturating_sub(start) / 2));
+    let vector_words = words.split_off(code_length);
+
+    let mut runs = Vec::new();
+    if !words.is_empty() {
+        runs.push(WordRun {
+            start,
+            words,
+            executable: segment.permissions.execute,
+            vectors: false,
+        });
+    }
+    if !vector_words.is_empty() && !options.ignore_ivt {
+        runs.push(WordRun {
+            start: start.wrapping_add(2 * code_length as u16),
+            words: vector_words,
+            executable: false,
+            vectors: true,
+        });
+    }
+    (runs, stray_bytes)
+}
+
+fn disassemble_op(
+    raw_words: &[u16],
+    address: u16,
+    options: &DisassemblyOptions,
+) -> (usize, Option<Instruction>, String) {
+    match decode_at(&raw_words[..raw_words.len().min(3)], address) {
+        Ok((instruction @ Instruction::Jump(jump_instruction), step)) => (
+            step,
+            Some(instruction),
+            format!(
+                "{} ({:#06x})",
+                jump_instruction,
+                jump_instruction.target(address)
+            ),
+        ),
+        Ok((instruction, step)) => (
+            step,
+            Some(instruction),
+            format_instruction(&instruction, address, options),
+        ),
+        // Show what we can of the instruction, the operands held by its missing words are unknown
+        Err(DecodeError::Truncated {
+            needed, available, ..
+        }) => {
+            let mut padded_words = raw_words.to_vec();
+            padded_words.resize(needed, 0);
+            let partial = match decode_at(&padded_words, address) {
+                Ok((instruction, _)) => truncated_text(&instruction, address, available),
+                Err(_) => format!(".word {:#06x}", raw_words[0]),
+            };
+            (
+                available,
+                None,
+                format!(
+                    "{} ; truncated, missing {} of {} words",
+                    partial,
+                    needed - available,
+                    needed
+                ),
+            )
+        }
+        // Undecodable words are most likely data, keep them as they are and move on
+        Err(_) => (1, None, format!(".word {:#06x}", raw_words[0])),
+    }
+}
+
+// Real form of an instruction decoded from zero filled words, the operands whose extension word
+// is missing are printed as `?`, as emulated forms and register names would depend on them
+fn truncated_text(instruction: &Instruction, address: u16, available: usize) -> String {
+    let missing_from = 2 * available as u16;
+    let operands = instruction
+        .operands_at(address)
+        .into_iter()
+        .map(
+            |(mode, extension_address)| match (mode, extension_address) {
+                (_, Some(extension)) if extension.wrapping_sub(address) >= missing_from => {
+                    match mode {
+                        AddresingMode::Immediate(_) => "#?".to_string(),
+                        AddresingMode::Absolute(_) => "&?".to_string(),
+                        AddresingMode::Symbolic(_) => "?(PC)".to_string(),
+                        AddresingMode::Indexed((_, register)) => {
+                            format!("?({})", String::from(register))
+                        }
+                        _ => mode.to_string(),
+                    }
+                }
+                _ => mode.to_string(),
+            },
+        )
+        .join(" ");
+    let mnemonic = match instruction {
+        Instruction::Jump(jump_instruction) => jump_instruction.operation().to_string(),
+        Instruction::OneOp(one_op_instruction) => match one_op_instruction.mode() {
+            Some(DataMode::Byte) => format!("{}.b", one_op_instruction.operation()),
+            _ => one_op_instruction.operation().to_string(),
+        },
+        Instruction::TwoOp(two_op_instruction) => match two_op_instruction.mode() {
+            DataMode::Byte => format!("{}.b", two_op_instruction.operation()),
+            DataMode::Word => two_op_instruction.operation().to_string(),
+        },
+    };
+    format!("{} {}", mnemonic, operands)
+}
+
+// Programmed vectors of the table, as pairs of vector address and handler address
+fn interrupt_vectors(vector_words: &[u16], table_base: u16) -> Vec<(u16, u16)> {
+    vector_words
+        .iter()
+        .enumerate()
+        .map(|(index, handler)| (table_base.wrapping_add(2 * index as u16), *handler))
+        .filter(|(_, handler)| *handler != UNUSED_VECTOR)
+        .collect()
+}
+
+fn vector_name(address: u16, device: Option<&Device>) -> String {
+    if let Some(name) = device.and_then(|device| device.vector_name(address)) {
+        return name.to_string();
+    }
+    match address {
+        RESET_VECTOR => "RESET".to_string(),
+        NMI_VECTOR => "NMI".to_string(),
+        _ => format!("VECTOR_{}", (address - INTERRUPT_VECTORS) / 2),
+    }
+}
+
+fn vector_text(
+    address: u16,
+    handler: &str,
+    handler_address: u16,
+    device: Option<&Device>,
+) -> String {
+    format!(
+        ".word {} ; {} -> {:#06x}",
+        handler,
+        vector_name(address, device),
+        handler_address
+    )
+}
+
+fn vector_line(address: u16, handler: u16, device: Option<&Device>) -> ListingLine {
+    let (vector, text) = if handler == UNUSED_VECTOR {
+        (
+            None,
+            format!(
+                ".word {:#06x} ; {} unused",
+                handler,
+                vector_name(address, device)
+            ),
+        )
+    } else {
+        (
+            Some(handler),
+            vector_text(address, &format!("{:#06x}", handler), handler, device),
+        )
+    };
+    ListingLine {
+        address,
+        bytes: handler.to_le_bytes().to_vec(),
+        instruction: None,
+        vector,
+        text,
+    }
+}
+
+// Follows the control flow from the interrupt handlers and the entry points, returning the addresses of the reachable instructions
+fn reachable_code(
+    runs: &[WordRun],
+    vectors: &[(u16, u16)],
+    options: &DisassemblyOptions,
+) -> HashSet<u16> {
+    let mut pending: Vec<u16> = vectors.iter().map(|(_, handler)| *handler).collect();
+    pending.extend(options.entry_points.iter().copied());
+
+    let mut code_starts: HashSet<u16> = HashSet::new();
+    while let Some(address) = pending.pop() {
+        if code_starts.contains(&address) {
+            continue;
+        }
+        let (run, index) = match runs
+            .iter()
+            .filter(|run| run.executable && !run.vectors)
+            .find_map(|run| Some((run, run.index(address)?)))
+        {
+            Some(found) => found,
+            None => continue,
+        };
+        let (instruction, step) =
+            match decode_at(&run.words[index..run.words.len().min(index + 3)], address) {
+                Ok(decoded) => decoded,
+                // Execution cannot go through words that are not instructions
+                Err(_) => continue,
+            };
+        code_starts.insert(address);
+        pending.extend(successors(
+            &instruction,
+            address,
+            address.wrapping_add(2 * step as u16),
+        ));
+    }
+    code_starts
+}
+
+// Addresses execution can continue at after `instruction`, as far as it can be known statically
+fn successors(instruction: &Instruction, address: u16, next: u16) -> Vec<u16> {
+    match instruction {
+        Instruction::Jump(jump_instruction) => {
+            let target = jump_instruction.target(address);
+            if jump_instruction.operation() == JumpOp::Jmp {
+                vec![target]
             } else {
-                print!("       ");
+                vec![target, next]
+            }
+        }
+        Instruction::OneOp(one_op_instruction) => match one_op_instruction.operation() {
+            OneOp::Reti => Vec::new(),
+            OneOp::Call => match one_op_instruction.data() {
+                AddresingMode::Immediate(target) => vec![target, next],
+                _ => vec![next],
+            },
+            _ => vec![next],
+        },
+        Instruction::TwoOp(two_op_instruction) => {
+            let writes_pc = two_op_instruction.destination() == AddresingMode::Direct(Register::Pc)
+                && !matches!(two_op_instruction.operation(), TwoOp::Cmp | TwoOp::Bit);
+            if !writes_pc {
+                return vec![next];
+            }
+            // Only `br #imm` has a known destination, `ret` and computed branches end the flow
+            match branch_target(instruction, address) {
+                Some((target, _)) => vec![target],
+                None => Vec::new(),
             }
         }
-        println!("{}", decoded_op);
-        first_pass_decoded.push(decoded_op);
+    }
+}
 
-        curr_word += step;
+// Address reached by a jump, `call #imm` or `br #imm`, with the kind of label it deserves
+fn branch_target(instruction: &Instruction, address: u16) -> Option<(u16, LabelKind)> {
+    match instruction {
+        Instruction::Jump(jump_instruction) => {
+            Some((jump_instruction.target(address), LabelKind::Location))
+        }
+        Instruction::OneOp(one_op_instruction) => {
+            match (one_op_instruction.operation(), one_op_instruction.data()) {
+                (OneOp::Call, AddresingMode::Immediate(target)) => {
+                    Some((target, LabelKind::Subroutine))
+                }
+                _ => None,
+            }
+        }
+        Instruction::TwoOp(two_op_instruction) => {
+            let emulated = two_op_instruction.emulated_form()?;
+            match (emulated.operation(), emulated.data()) {
+                (EmulatedOp::Br, Some(AddresingMode::Immediate(target))) => {
+                    Some((target, LabelKind::Location))
+                }
+                _ => None,
+            }
+        }
     }
-    Ok(first_pass_decoded)
 }
 
-fn disassemble_op(raw_words: &[u16]) -> (usize, String) {
-    let jmp_bits: u16 = raw_words[0].shr(13);
-    if jmp_bits == 0b001 {
-        let jump_instruction = jumps::JumpInstruction::new(raw_words[0]);
-        return (1, format!("{}", jump_instruction));
-    };
-    let one_op_bits: u16 = raw_words[0].shr(12);
-    if one_op_bits == 0b0001 || one_op_bits == 0 {
-        let (one_op_instruction, extra_word) = one_op::OneOpInstruction::new(raw_words);
-        return (
-            if extra_word { 2 } else { 1 },
-            format!("{}", one_op_instruction),
-        );
+// Targets outside of the listing, or in the middle of an instruction, keep their address
+fn collect_labels(lines: &[ListingLine], symbols: &BTreeMap<u16, String>) -> BTreeMap<u16, String> {
+    let line_starts: HashSet<u16> = lines.iter().map(|line| line.address).collect();
+
+    let mut kinds: BTreeMap<u16, LabelKind> = BTreeMap::new();
+    for line in lines {
+        // Interrupt handlers are named like subroutines
+        if let Some((target, kind)) = line
+            .instruction
+            .and_then(|instruction| branch_target(&instruction, line.address))
+            .or_else(|| line.vector.map(|handler| (handler, LabelKind::Subroutine)))
+        {
+            if line_starts.contains(&target) {
+                // A called location is a subroutine even if it is also jumped to
+                let entry = kinds.entry(target).or_insert(kind);
+                *entry = (*entry).max(kind);
+            }
+        }
+    }
+
+    let mut labels: BTreeMap<u16, String> = kinds
+        .into_iter()
+        .map(|(target, kind)| (target, kind.name(target)))
+        .collect();
+    labels.extend(
+        symbols
+            .iter()
+            .filter(|(address, _)| line_starts.contains(address))
+            .map(|(address, name)| (*address, name.clone())),
+    );
+    labels
+}
+
+fn labelled_text(
+    instruction: &Instruction,
+    address: u16,
+    labels: &BTreeMap<u16, String>,
+) -> Option<String> {
+    let (target, _) = branch_target(instruction, address)?;
+    let label = labels.get(&target)?;
+    match instruction {
+        Instruction::Jump(jump_instruction) => {
+            Some(format!("{} {}", jump_instruction.operation(), label))
+        }
+        Instruction::OneOp(one_op_instruction) => {
+            Some(format!("{} #{}", one_op_instruction.operation(), label))
+        }
+        Instruction::TwoOp(_) => Some(format!("{} #{}", EmulatedOp::Br, label)),
     }
-    let (two_op_instruction, extra_words) = two_op::TwoOpInstruction::new(raw_words);
-    let op_string = if let Some(emulated) = two_op_instruction.emulated_form() {
-        format!("{}", emulated)
+}
+
+// Symbolic operands are shown relative to PC, followed by the address they resolve to
+fn format_instruction(
+    instruction: &Instruction,
+    address: u16,
+    options: &DisassemblyOptions,
+) -> String {
+    let registers = &options.registers;
+    let mut comments = instruction
+        .operands_at(address)
+        .into_iter()
+        .filter(|(mode, _)| matches!(mode, AddresingMode::Symbolic(_)))
+        .filter_map(|(mode, extension_address)| effective_address(mode, extension_address))
+        .dedup()
+        .map(|target| match registers.name(target) {
+            Some(name) => format!("={}", name),
+            None => format!("={:#06x}", target),
+        })
+        .collect_vec();
+    // Constants written to a register are spelled as its bitfields
+    if let Instruction::TwoOp(two_op_instruction) = instruction {
+        if let (AddresingMode::Immediate(value), AddresingMode::Absolute(register)) = (
+            two_op_instruction.source(),
+            two_op_instruction.destination(),
+        ) {
+            comments.extend(registers.describe_bits(register, value));
+        }
+    }
+    if let Some(stack) = &options.stack {
+        comments.extend(stack_comment(instruction, address, stack));
+    }
+    let resolved = comments.join(", ");
+
+    let text = instruction.to_string_with(registers);
+    if resolved.is_empty() {
+        text
     } else {
-        format!("{}", two_op_instruction)
-    };
-    (1 + usize::from(extra_words), op_string)
+        format!("{} ; {}", text, resolved)
+    }
+}
+
+// Initialisations of SP and memory accesses falling in the stack region
+fn stack_comment(instruction: &Instruction, address: u16, stack: &StackRegion) -> Option<String> {
+    if let Instruction::TwoOp(two_op_instruction) = instruction {
+        if let (TwoOp::Mov, AddresingMode::Immediate(value), AddresingMode::Direct(Register::Sp)) = (
+            two_op_instruction.operation(),
+            two_op_instruction.source(),
+            two_op_instruction.destination(),
+        ) {
+            let position = if value == stack.begin() {
+                "stack pointer set to the top of the stack"
+            } else if stack.contains(value) {
+                "stack pointer set inside the stack"
+            } else {
+                "stack pointer set outside of the stack"
+            };
+            return Some(position.to_string());
+        }
+    }
+
+    // Other registers may point anywhere, only fixed addresses are checked against the region
+    instruction
+        .operands_at(address)
+        .into_iter()
+        .any(|(mode, extension_address)| match mode {
+            AddresingMode::Indexed((_, Register::Sp))
+            | AddresingMode::Indirect(Register::Sp)
+            | AddresingMode::Autoincrement(Register::Sp) => true,
+            _ => effective_address(mode, extension_address)
+                .is_some_and(|target| stack.contains(target)),
+        })
+        .then(|| "stack access".to_string())
+}
+
+fn write_header<W: Write>(writer: &mut W, options: &DisassemblyOptions) -> io::Result<()> {
+    if let Some(device) = &options.device {
+        writeln!(writer, "; {}", device.name)?;
+        for range in device.memory.iter() {
+            writeln!(
+                writer,
+                "; {:<8}{:<12}{:#06x}-{:#06x}",
+                range.name,
+                range.kind.to_string(),
+                range.start,
+                range.end
+            )?;
+        }
+    }
+    if let Some(stack) = &options.stack {
+        // The kind of memory holding the stack is only known from the device
+        let kind = options
+            .device
+            .as_ref()
+            .and_then(|device| device.memory_range(stack.end()))
+            .map_or(String::new(), |range| range.kind.to_string());
+        writeln!(
+            writer,
+            "; {:<8}{:<12}{:#06x}-{:#06x} ({} bytes)",
+            "STACK",
+            kind,
+            stack.end(),
+            stack.begin() - 1,
+            stack.size()
+        )?;
+    }
+    if options.device.is_some() || options.stack.is_some() {
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+// Raw bytes are listed in memory order, as MSProbe does
+fn write_line<W: Write>(
+    writer: &mut W,
+    line: &ListingLine,
+    layout: &ListingLayout,
+) -> io::Result<()> {
+    if layout.address {
+        write!(writer, "{:04x}: ", line.address)?;
+    }
+    if layout.raw_bytes {
+        let raw_column = line
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .join(" ");
+        write!(writer, "{:<width$}", raw_column, width = RAW_COLUMN_WIDTH)?;
+    }
+    writeln!(writer, "{}", line.text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(pc_base: u16) -> DisassemblyOptions {
+        DisassemblyOptions {
+            pc_base,
+            quiet: true,
+            ..Default::default()
+        }
+    }
+
+    fn listing(bytes: &[u8], options: &DisassemblyOptions) -> Vec<String> {
+        let mut output = Vec::new();
+        disassemble(&mut &bytes[..], &mut output, options).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn undecodable_words_are_listed_as_data() {
+        assert_eq!(
+            listing(&[0x00, 0x00, 0x80, 0x13], &options(0)),
+            vec![
+                "0000: 00 00             .word 0x0000",
+                "0002: 80 13             .word 0x1380",
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_instructions_show_their_missing_operands() {
+        assert_eq!(
+            listing(&[0x03, 0x43, 0xb2, 0x40], &options(0)),
+            vec![
+                "0000: 03 43             nop",
+                "0002: b2 40             mov #? &? ; truncated, missing 2 of 3 words",
+            ]
+        );
+    }
+
+    #[test]
+    fn odd_trailing_bytes_are_listed_as_data() {
+        assert_eq!(
+            listing(&[0x03, 0x43, 0x30], &options(0)),
+            vec![
+                "0000: 03 43             nop",
+                "0002: 30                .byte 0x30"
+            ]
+        );
+    }
+
+    #[test]
+    fn odd_trailing_bytes_can_be_padded() {
+        let padded = DisassemblyOptions {
+            pad: true,
+            ..options(0)
+        };
+        assert_eq!(
+            listing(&[0x03, 0x43, 0x30], &padded),
+            vec![
+                "0000: 03 43             nop",
+                "0002: 30 00             .word 0x0030"
+            ]
+        );
+    }
+
+    #[test]
+    fn jumps_show_their_offset_and_target() {
+        assert_eq!(
+            listing(&[0x03, 0x43, 0xfd, 0x23], &options(0xc000)),
+            vec![
+                "c000: 03 43             nop",
+                "c002: fd 23             jne $-0x4 (0xbffe)",
+            ]
+        );
+    }
+
+    // mov #0x4400, SP; call #0xc00e; dec R5; jne $-0x2; jmp $; ret; .word 0x1234
+    const PROGRAM: &[u8] = &[
+        0x31, 0x40, 0x00, 0x44, 0xb0, 0x12, 0x0e, 0xc0, 0x15, 0x83, 0xfe, 0x23, 0xff, 0x3f, 0x30,
+        0x41, 0x34, 0x12,
+    ];
+
+    #[test]
+    fn branch_targets_are_labelled() {
+        assert_eq!(
+            listing(PROGRAM, &options(0xc000)),
+            vec![
+                "c000: 31 40 00 44       mov #0x4400 (17408) SP",
+                "c004: b0 12 0e c0       call #sub_c00e",
+                "loc_c008:",
+                "c008: 15 83             dec R5",
+                "c00a: fe 23             jne loc_c008",
+                "loc_c00c:",
+                "c00c: ff 3f             jmp loc_c00c",
+                "sub_c00e:",
+                "c00e: 30 41             ret",
+                "c010: 34 12             push @R4+",
+            ]
+        );
+    }
+
+    #[test]
+    fn unreached_words_are_data_in_recursive_mode() {
+        let recursive = DisassemblyOptions {
+            recursive: true,
+            ..options(0xc000)
+        };
+        let lines = listing(PROGRAM, &recursive);
+        assert_eq!(
+            lines.last().unwrap(),
+            "c010: 34 12             .word 0x1234"
+        );
+        assert!(lines.contains(&"c00e: 30 41             ret".to_string()));
+    }
+
+    // nop; jmp $; then the vector table, with only RESET programmed
+    fn vector_table_image() -> Vec<u8> {
+        let mut bytes = vec![0x03, 0x43, 0xff, 0x3f];
+        bytes.extend([0xff; 30]);
+        bytes.extend([0xdc, 0xff]);
+        bytes
+    }
+
+    #[test]
+    fn vector_table_is_listed() {
+        let lines = listing(&vector_table_image(), &options(0xffdc));
+        assert_eq!(lines[0], "sub_ffdc:");
+        assert_eq!(
+            lines[4],
+            "ffe0: ff ff             .word 0xffff ; VECTOR_0 unused"
+        );
+        assert_eq!(
+            lines[18],
+            "fffc: ff ff             .word 0xffff ; NMI unused"
+        );
+        assert_eq!(
+            lines[19],
+            "fffe: dc ff             .word sub_ffdc ; RESET -> 0xffdc"
+        );
+    }
+
+    #[test]
+    fn vector_table_can_be_ignored() {
+        let ignored = DisassemblyOptions {
+            ignore_ivt: true,
+            ..options(0xffdc)
+        };
+        assert_eq!(
+            listing(&vector_table_image(), &ignored),
+            vec![
+                "ffdc: 03 43             nop",
+                "loc_ffde:",
+                "ffde: ff 3f             jmp loc_ffde",
+            ]
+        );
+    }
+
+    #[test]
+    fn stack_regions_are_validated() {
+        let region = StackRegion::new(0x400, None, Some(0x100)).unwrap();
+        assert_eq!((region.end(), region.size()), (0x300, 0x100));
+        assert_eq!(StackRegion::new(0x400, Some(0x300), None), Ok(region));
+        assert!(region.contains(0x3fe) && !region.contains(0x400));
+
+        assert!(StackRegion::new(0x400, None, None).is_err());
+        assert!(StackRegion::new(0x400, Some(0x300), Some(0x100)).is_err());
+        assert!(StackRegion::new(0x100, None, Some(0x200)).is_err());
+        assert!(StackRegion::new(0x400, Some(0x400), None).is_err());
+        assert!(StackRegion::new(0x400, Some(0x500), None).is_err());
+    }
 }
@@ -0,0 +1,163 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+pub mod elf;
+pub mod ihex;
+pub mod microcorruption;
+pub mod srec;
+pub mod titxt;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Record { line: usize, message: String },
+    Invalid(&'static str),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "unable to read the input: {}", error),
+            Self::Record { line, message } => write!(f, "line {}: {}", line, message),
+            Self::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+// Pairs of hexadecimal digits, as used by every textual format
+pub(crate) fn parse_hex_bytes(text: &str) -> Result<Vec<u8>, &'static str> {
+    if !text.len().is_multiple_of(2) {
+        return Err("odd number of hexadecimal digits");
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| {
+            text.get(index..index + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or("invalid hexadecimal digits")
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Binary,
+    IntelHex,
+    TiTxt,
+    Srec,
+    Elf,
+    Microcorruption,
+}
+
+impl FromStr for InputFormat {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "binary" | "bin" => Ok(Self::Binary),
+            "ihex" | "intel-hex" => Ok(Self::IntelHex),
+            "ti-txt" | "titxt" => Ok(Self::TiTxt),
+            "srec" => Ok(Self::Srec),
+            "elf" => Ok(Self::Elf),
+            "microcorruption" => Ok(Self::Microcorruption),
+            _ => Err("unknown format, expected one of binary, ihex, ti-txt, srec, elf or microcorruption"),
+        }
+    }
+}
+
+// Guesses the format of the whole input. Text formats are recognized by their first line, anything
+// else is raw binary. Pipes may deliver the first line in pieces, so a prefix is not enough.
+pub fn detect(input: &[u8]) -> InputFormat {
+    if input.starts_with(b"\x7fELF") {
+        return InputFormat::Elf;
+    }
+
+    let first_line = input
+        .split(|byte| *byte == b'\n')
+        .map(|line| line.trim_ascii())
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    if !first_line
+        .iter()
+        .all(|byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace())
+    {
+        return InputFormat::Binary;
+    }
+    let is_hex = |bytes: &[u8]| !bytes.is_empty() && bytes.iter().all(u8::is_ascii_hexdigit);
+
+    match first_line {
+        [b':', digits @ ..] if is_hex(digits) => InputFormat::IntelHex,
+        [b'@', digits @ ..] if is_hex(digits) => InputFormat::TiTxt,
+        [b'S' | b's', kind, digits @ ..] if kind.is_ascii_digit() && is_hex(digits) => {
+            InputFormat::Srec
+        }
+        // Rows of a dump start with a four digits address, as in `4400:`
+        [address @ .., b':'] if address.len() == 4 && is_hex(address) => {
+            InputFormat::Microcorruption
+        }
+        _ => match first_line.iter().position(|byte| *byte == b':') {
+            Some(4) if is_hex(&first_line[..4]) => InputFormat::Microcorruption,
+            _ => InputFormat::Binary,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    // Hands out its data a few bytes at a time, as a slow pipe does
+    struct ChunkedReader<'a> {
+        data: &'a [u8],
+    }
+
+    impl Read for ChunkedReader<'_> {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let length = self.data.len().min(buffer.len()).min(4);
+            buffer[..length].copy_from_slice(&self.data[..length]);
+            self.data = &self.data[length..];
+            Ok(length)
+        }
+    }
+
+    #[test]
+    fn detects_each_format_from_its_first_line() {
+        assert_eq!(detect(b"\x7fELF\x01\x01\x01"), InputFormat::Elf);
+        assert_eq!(detect(b":04C000003140004487\n"), InputFormat::IntelHex);
+        assert_eq!(detect(b"@C000\n31 40 00 44\nq\n"), InputFormat::TiTxt);
+        assert_eq!(detect(b"S0030000FC\n"), InputFormat::Srec);
+        assert_eq!(
+            detect(b"4400:   3140 0044 1542 5c01   1@.D.B\\.\n"),
+            InputFormat::Microcorruption
+        );
+        assert_eq!(detect(b"0010:   *\n"), InputFormat::Microcorruption);
+        assert_eq!(detect(b"\x31\x40\x00\x44\xb0\x12"), InputFormat::Binary);
+    }
+
+    #[test]
+    fn leading_blank_lines_are_skipped() {
+        assert_eq!(detect(b"\n\n:00000001FF\n"), InputFormat::IntelHex);
+    }
+
+    #[test]
+    fn inputs_read_in_small_chunks_are_detect() {
+        let dump = b"4400:   3140 0044 1542 5c01 75f3 35d0 085a 3f40   1@.D.B\\.u.5..Z?@\n";
+        let mut input = Vec::new();
+        ChunkedReader { data: dump }
+            .read_to_end(&mut input)
+            .unwrap();
+        assert_eq!(detect(&input), InputFormat::Microcorruption);
+        // The first read alone is not enough to recognise the dump
+        assert_eq!(detect(&dump[..4]), InputFormat::Binary);
+    }
+}
@@ -0,0 +1,311 @@
+use std::io::Read;
+
+use crate::formats::LoadError;
+use crate::image::{MemoryImage, Permissions};
+
+const MAGIC: &[u8] = b"\x7fELF";
+const CLASS_32: u8 = 1;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const EM_MSP430: u16 = 0x69;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 0x1;
+const PF_W: u32 = 0x2;
+const PF_R: u32 = 0x4;
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+const SHF_ALLOC: u32 = 0x2;
+const SHF_EXECINSTR: u32 = 0x4;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+const PROGRAM_HEADER_SIZE: usize = 32;
+const SECTION_HEADER_SIZE: usize = 40;
+const SYMBOL_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Object,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u16,
+    pub kind: SymbolKind,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ElfImage {
+    // Contents of the loadable segments, at their physical addresses
+    pub image: MemoryImage,
+    pub symbols: Vec<Symbol>,
+    // Address ranges of the executable sections in the image, end excluded
+    pub executable: Vec<(u32, u32)>,
+}
+
+// Bounds checked little endian accessors over the whole file
+struct ElfData<'a> {
+    bytes: &'a [u8],
+}
+
+impl ElfData<'_> {
+    fn slice(&self, offset: usize, size: usize) -> Result<&[u8], LoadError> {
+        offset
+            .checked_add(size)
+            .and_then(|end| self.bytes.get(offset..end))
+            .ok_or(LoadError::Invalid("the ELF file is truncated"))
+    }
+
+    fn u8(&self, offset: usize) -> Result<u8, LoadError> {
+        Ok(self.slice(offset, 1)?[0])
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, LoadError> {
+        let bytes = self.slice(offset, 2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, LoadError> {
+        let bytes = self.slice(offset, 4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn offset(&self, offset: usize) -> Result<usize, LoadError> {
+        Ok(self.u32(offset)? as usize)
+    }
+
+    // Null terminated string starting at `offset`
+    fn string(&self, offset: usize) -> Result<String, LoadError> {
+        let tail = self
+            .bytes
+            .get(offset..)
+            .ok_or(LoadError::Invalid("the ELF file is truncated"))?;
+        let length = tail
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or(LoadError::Invalid("unterminated string in the ELF file"))?;
+        Ok(String::from_utf8_lossy(&tail[..length]).into_owned())
+    }
+}
+
+struct SectionHeader {
+    kind: u32,
+    flags: u32,
+    address: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+}
+
+pub fn read<R: Read>(reader: &mut R) -> Result<ElfImage, LoadError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let elf = ElfData { bytes: &bytes };
+
+    if elf.slice(0, MAGIC.len()).ok() != Some(MAGIC) {
+        return Err(LoadError::Invalid("not an ELF file"));
+    }
+    if elf.u8(4)? != CLASS_32 || elf.u8(5)? != DATA_LITTLE_ENDIAN {
+        return Err(LoadError::Invalid(
+            "only 32 bits little endian ELF files are supported",
+        ));
+    }
+    if elf.u16(18)? != EM_MSP430 {
+        return Err(LoadError::Invalid(
+            "the ELF file does not target the MSP430",
+        ));
+    }
+
+    let program_headers = elf.offset(28)?;
+    let section_headers = elf.offset(32)?;
+    let program_header_count = usize::from(elf.u16(44)?);
+    let section_header_count = usize::from(elf.u16(48)?);
+
+    let mut loaded = ElfImage::default();
+    // Virtual address, physical address and memory size of the loadable segments
+    let mut segments: Vec<(u32, u32, u32)> = Vec::new();
+    for index in 0..program_header_count {
+        let header = program_headers + index * PROGRAM_HEADER_SIZE;
+        if elf.u32(header)? != PT_LOAD {
+            continue;
+        }
+        let offset = elf.offset(header + 4)?;
+        let virtual_address = elf.u32(header + 8)?;
+        let physical_address = elf.u32(header + 12)?;
+        // Only the initialised part is in the file, the rest is zeroed at startup
+        let file_size = elf.offset(header + 16)?;
+        let flags = elf.u32(header + 24)?;
+        segments.push((virtual_address, physical_address, elf.u32(header + 20)?));
+        if file_size == 0 {
+            continue;
+        }
+        if u64::from(physical_address) + file_size as u64 > 0x10000 {
+            return Err(LoadError::Invalid(
+                "a segment is outside of the 16 bits address space",
+            ));
+        }
+        let permissions = Permissions {
+            read: flags & PF_R != 0,
+            write: flags & PF_W != 0,
+            execute: flags & PF_X != 0,
+        };
+        loaded
+            .image
+            .insert_with(
+                physical_address as u16,
+                elf.slice(offset, file_size)?,
+                permissions,
+            )
+            .map_err(LoadError::Invalid)?;
+    }
+
+    let sections = (0..section_header_count)
+        .map(|index| {
+            let header = section_headers + index * SECTION_HEADER_SIZE;
+            Ok(SectionHeader {
+                kind: elf.u32(header + 4)?,
+                flags: elf.u32(header + 8)?,
+                address: elf.u32(header + 12)?,
+                offset: elf.offset(header + 16)?,
+                size: elf.offset(header + 20)?,
+                link: elf.offset(header + 24)?,
+            })
+        })
+        .collect::<Result<Vec<SectionHeader>, LoadError>>()?;
+
+    for section in sections.iter() {
+        if section.flags & (SHF_ALLOC | SHF_EXECINSTR) == SHF_ALLOC | SHF_EXECINSTR
+            && section.kind != SHT_NOBITS
+            && section.size > 0
+        {
+            // Sections are linked at their virtual address, but loaded at their physical one.
+            // The header fields are not trusted, so the arithmetic is done on 64 bits.
+            let address = u64::from(section.address);
+            let start = segments
+                .iter()
+                .map(|(virtual_address, physical_address, memory_size)| {
+                    (
+                        u64::from(*virtual_address),
+                        u64::from(*physical_address),
+                        u64::from(*memory_size),
+                    )
+                })
+                .find(|(virtual_address, _, memory_size)| {
+                    (*virtual_address..virtual_address + memory_size).contains(&address)
+                })
+                .map_or(address, |(virtual_address, physical_address, _)| {
+                    physical_address + (address - virtual_address)
+                });
+            let end = start + section.size as u64;
+            match (u32::try_from(start), u32::try_from(end)) {
+                (Ok(start), Ok(end)) => loaded.executable.push((start, end)),
+                _ => {
+                    return Err(LoadError::Invalid(
+                        "a section is outside of the 32 bits address space",
+                    ))
+                }
+            }
+        }
+    }
+
+    for symbol_table in sections.iter().filter(|section| section.kind == SHT_SYMTAB) {
+        let names = sections
+            .get(symbol_table.link)
+            .ok_or(LoadError::Invalid("the symbol table has no string table"))?;
+        for index in 0..symbol_table.size / SYMBOL_SIZE {
+            let symbol = symbol_table.offset + index * SYMBOL_SIZE;
+            let kind = match elf.u8(symbol + 12)? & 0xf {
+                STT_FUNC => SymbolKind::Function,
+                STT_OBJECT => SymbolKind::Object,
+                _ => continue,
+            };
+            let name = elf.string(names.offset + elf.offset(symbol)?)?;
+            let address = match u16::try_from(elf.u32(symbol + 4)?) {
+                Ok(address) if !name.is_empty() => address,
+                _ => continue,
+            };
+            loaded.symbols.push(Symbol {
+                name,
+                address,
+                kind,
+            });
+        }
+    }
+
+    Ok(loaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Header and a single loadable segment holding `mov #0x4400, SP` at 0xc000
+    fn minimal_elf(machine: u16) -> Vec<u8> {
+        let mut bytes = vec![0u8; 84];
+        bytes[..4].copy_from_slice(MAGIC);
+        bytes[4] = CLASS_32;
+        bytes[5] = DATA_LITTLE_ENDIAN;
+        bytes[6] = 1;
+        bytes[18..20].copy_from_slice(&machine.to_le_bytes());
+        bytes[28..32].copy_from_slice(&52u32.to_le_bytes());
+        bytes[44..46].copy_from_slice(&1u16.to_le_bytes());
+
+        let header = [PT_LOAD, 84, 0xc000, 0xc000, 4, 4, PF_R | PF_X, 2];
+        for (index, value) in header.iter().enumerate() {
+            bytes[52 + 4 * index..56 + 4 * index].copy_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[0x31, 0x40, 0x00, 0x44]);
+        bytes
+    }
+
+    #[test]
+    fn reads_loadable_segments() {
+        let loaded = read(&mut minimal_elf(EM_MSP430).as_slice()).unwrap();
+        let segment = &loaded.image.segments()[0];
+        assert_eq!(segment.start, 0xc000);
+        assert_eq!(segment.data, vec![0x31, 0x40, 0x00, 0x44]);
+        assert!(segment.permissions.execute && !segment.permissions.write);
+    }
+
+    #[test]
+    fn rejects_other_architectures() {
+        let result = read(&mut minimal_elf(0x28).as_slice());
+        assert!(matches!(result, Err(LoadError::Invalid(_))));
+    }
+
+    #[test]
+    fn rejects_sections_past_the_address_space() {
+        let mut bytes = minimal_elf(EM_MSP430);
+        // Virtual address of the segment, then a text section ending past 4 GiB
+        bytes[60..64].copy_from_slice(&0xfffffff0u32.to_le_bytes());
+        let section_headers = bytes.len();
+        bytes[32..36].copy_from_slice(&(section_headers as u32).to_le_bytes());
+        bytes[48..50].copy_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&[0; SECTION_HEADER_SIZE]);
+        let section = [
+            0,
+            1,
+            SHF_ALLOC | SHF_EXECINSTR,
+            0xfffffff8,
+            84,
+            0x10,
+            0,
+            0,
+            2,
+            0,
+        ];
+        for value in section {
+            bytes.extend_from_slice(&u32::to_le_bytes(value));
+        }
+        let result = read(&mut bytes.as_slice());
+        assert!(matches!(result, Err(LoadError::Invalid(_))));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let result = read(&mut &minimal_elf(EM_MSP430)[..60]);
+        assert!(matches!(result, Err(LoadError::Invalid(_))));
+    }
+}
@@ -0,0 +1,89 @@
+use std::io::BufRead;
+
+use crate::formats::{parse_hex_bytes, LoadError};
+use crate::image::MemoryImage;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+// Loads the `:LLAAAATT...CC` records of an Intel HEX file, reading stops at the end of file record
+pub fn read<R: BufRead>(reader: &mut R) -> Result<MemoryImage, LoadError> {
+    let mut image = MemoryImage::default();
+    // Added to the record addresses by the extended address records
+    let mut base_address: u32 = 0;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let error = |message: &str| LoadError::Record {
+            line: index + 1,
+            message: message.to_string(),
+        };
+
+        let record = line.trim();
+        if record.is_empty() {
+            continue;
+        }
+        let bytes = record
+            .strip_prefix(':')
+            .ok_or_else(|| error("records must start with ':'"))
+            .and_then(|digits| parse_hex_bytes(digits).map_err(error))?;
+        if bytes.len() < 5 || bytes.len() != usize::from(bytes[0]) + 5 {
+            return Err(error("the record length does not match its byte count"));
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(error("checksum mismatch"));
+        }
+
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]);
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            DATA => {
+                let full_address = base_address + u32::from(address);
+                if full_address + data.len() as u32 > 0x10000 {
+                    return Err(error("the data is outside of the 16 bits address space"));
+                }
+                image.insert(full_address as u16, data).map_err(error)?;
+            }
+            END_OF_FILE => break,
+            EXTENDED_SEGMENT_ADDRESS | EXTENDED_LINEAR_ADDRESS => {
+                let value = match data {
+                    [high, low] => u32::from(u16::from_be_bytes([*high, *low])),
+                    _ => return Err(error("extended address records hold two bytes")),
+                };
+                base_address = if bytes[3] == EXTENDED_SEGMENT_ADDRESS {
+                    value << 4
+                } else {
+                    value << 16
+                };
+            }
+            // The entry point is not needed to build the image
+            START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => {}
+            _ => return Err(error("unknown record type")),
+        }
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_data_records() {
+        let image = read(&mut ":04C000003140004487\n:00000001FF\n".as_bytes()).unwrap();
+        assert_eq!(
+            image,
+            MemoryImage::from_bytes(0xc000, vec![0x31, 0x40, 0x00, 0x44])
+        );
+    }
+
+    #[test]
+    fn rejects_bad_checksums() {
+        let result = read(&mut ":04C000003140004488\n".as_bytes());
+        assert!(matches!(result, Err(LoadError::Record { line: 1, .. })));
+    }
+}
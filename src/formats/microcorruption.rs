@@ -0,0 +1,88 @@
+use std::io::BufRead;
+
+use crate::formats::{parse_hex_bytes, LoadError};
+use crate::image::MemoryImage;
+
+const ROW_SIZE: usize = 16;
+const MEMORY_SIZE: usize = 0x10000;
+
+// Loads the memory dump printed by the Microcorruption debugger, rows like
+// `4400:   3140 0044 1542 5c01 75f3 35d0 085a 3f40   1@.D.B\.u.5..Z?@`
+// where a `*` row stands for zeroed rows up to the next listed address, or the end of memory.
+// Memory missing from the dump is zero, the image always spans the whole address space.
+pub fn read<R: BufRead>(reader: &mut R) -> Result<MemoryImage, LoadError> {
+    let mut memory = vec![0u8; MEMORY_SIZE];
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let error = |message: &str| LoadError::Record {
+            line: index + 1,
+            message: message.to_string(),
+        };
+
+        let content = line.trim();
+        if content.is_empty() {
+            continue;
+        }
+        let (address, row) = content
+            .split_once(':')
+            .ok_or_else(|| error("rows must start with an address followed by ':'"))?;
+        let address = usize::from(
+            u16::from_str_radix(address.trim(), 16).map_err(|_| error("invalid row address"))?,
+        );
+
+        // Zeroed rows are already in place
+        let row = row.trim();
+        if row.starts_with('*') {
+            continue;
+        }
+
+        // The ASCII column follows the words, which are always four hexadecimal digits
+        let bytes = row
+            .split_whitespace()
+            .take(ROW_SIZE / 2)
+            .take_while(|word| word.len() == 4 && word.chars().all(|c| c.is_ascii_hexdigit()))
+            .map(parse_hex_bytes)
+            .collect::<Result<Vec<Vec<u8>>, &str>>()
+            .map_err(error)?
+            .concat();
+        if bytes.is_empty() {
+            return Err(error("the row holds no data"));
+        }
+        if address + bytes.len() > MEMORY_SIZE {
+            return Err(error("the row goes past the end of the address space"));
+        }
+        memory[address..address + bytes.len()].copy_from_slice(&bytes);
+    }
+
+    Ok(MemoryImage::from_bytes(0, memory))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &str = "\
+0000:   0000 4400 0000 0000 0000 0000 0000 0000   ..D.............
+0010:   *
+4400:   3140 0044 1542 5c01 75f3 35d0 085a 3f40   1@.D.B\\.u.5..Z?@
+4410:   *
+";
+
+    #[test]
+    fn reads_rows_and_zeroed_runs() {
+        let image = read(&mut DUMP.as_bytes()).unwrap();
+        let memory = &image.segments()[0].data;
+        assert_eq!(memory.len(), MEMORY_SIZE);
+        assert_eq!(memory[0x02..0x04], [0x44, 0x00]);
+        assert_eq!(memory[0x4400..0x4404], [0x31, 0x40, 0x00, 0x44]);
+        assert!(memory[0x10..0x4400].iter().all(|byte| *byte == 0));
+        assert!(memory[0x4410..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn rejects_rows_without_data() {
+        let result = read(&mut "4400:   1@.D\n".as_bytes());
+        assert!(matches!(result, Err(LoadError::Record { line: 1, .. })));
+    }
+}
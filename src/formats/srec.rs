@@ -0,0 +1,119 @@
+use std::io::{self, BufRead, Write};
+
+use crate::formats::{parse_hex_bytes, LoadError};
+use crate::image::MemoryImage;
+
+const BYTES_PER_RECORD: usize = 16;
+
+// Ones' complement of the sum of the byte count, address and data bytes
+fn checksum(bytes: &[u8]) -> u8 {
+    !bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+// Loads the data records (S1, S2 and S3) of a Motorola S-record file, up to its termination record
+pub fn read<R: BufRead>(reader: &mut R) -> Result<MemoryImage, LoadError> {
+    let mut image = MemoryImage::default();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let error = |message: &str| LoadError::Record {
+            line: index + 1,
+            message: message.to_string(),
+        };
+
+        let record = line.trim();
+        if record.is_empty() {
+            continue;
+        }
+        let mut chars = record.chars();
+        if !matches!(chars.next(), Some('S' | 's')) {
+            return Err(error("records must start with 'S'"));
+        }
+        let record_type = chars
+            .next()
+            .and_then(|digit| digit.to_digit(10))
+            .ok_or_else(|| error("invalid record type"))?;
+        let bytes = parse_hex_bytes(chars.as_str()).map_err(error)?;
+        if bytes.len() < 2 || bytes.len() != usize::from(bytes[0]) + 1 {
+            return Err(error("the record length does not match its byte count"));
+        }
+        let (content, expected) = bytes.split_at(bytes.len() - 1);
+        if checksum(content) != expected[0] {
+            return Err(error("checksum mismatch"));
+        }
+
+        let address_size = match record_type {
+            // Header and record counts carry no memory contents
+            0 | 5 | 6 => continue,
+            1 => 2,
+            2 => 3,
+            3 => 4,
+            7..=9 => break,
+            _ => return Err(error("unknown record type")),
+        };
+        if content.len() < 1 + address_size {
+            return Err(error("the record is too short for its address"));
+        }
+        let address = content[1..=address_size]
+            .iter()
+            .fold(0u32, |address, byte| (address << 8) | u32::from(*byte));
+        let data = &content[1 + address_size..];
+        if address + data.len() as u32 > 0x10000 {
+            return Err(error("the data is outside of the 16 bits address space"));
+        }
+        image.insert(address as u16, data).map_err(error)?;
+    }
+    Ok(image)
+}
+
+fn write_record<W: Write>(writer: &mut W, record_type: u8, content: &[u8]) -> io::Result<()> {
+    let mut bytes = vec![(content.len() + 1) as u8];
+    bytes.extend_from_slice(content);
+    bytes.push(checksum(&bytes));
+    let digits: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    writeln!(writer, "S{}{}", record_type, digits)
+}
+
+// S1 data records with 16 bits addresses, closed by an S9 record
+pub fn write<W: Write>(image: &MemoryImage, writer: &mut W) -> io::Result<()> {
+    write_record(writer, 0, &[0, 0])?;
+    for segment in image.segments() {
+        for (index, chunk) in segment.data.chunks(BYTES_PER_RECORD).enumerate() {
+            let address = segment
+                .start
+                .wrapping_add((index * BYTES_PER_RECORD) as u16);
+            let mut content = address.to_be_bytes().to_vec();
+            content.extend_from_slice(chunk);
+            write_record(writer, 1, &content)?;
+        }
+    }
+    write_record(writer, 9, &[0, 0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_data_records() {
+        let image = read(&mut "S0030000FC\nS107C0003140004483\nS9030000FC\n".as_bytes()).unwrap();
+        assert_eq!(
+            image,
+            MemoryImage::from_bytes(0xc000, vec![0x31, 0x40, 0x00, 0x44])
+        );
+    }
+
+    #[test]
+    fn rejects_bad_checksums() {
+        let result = read(&mut "S0030000FC\nS107C0003140004484\n".as_bytes());
+        assert!(matches!(result, Err(LoadError::Record { line: 2, .. })));
+    }
+
+    #[test]
+    fn written_records_read_back() {
+        let image = MemoryImage::from_bytes(0xc000, (0..40).collect());
+        let mut written = Vec::new();
+        write(&image, &mut written).unwrap();
+        assert_eq!(read(&mut written.as_slice()).unwrap(), image);
+    }
+}
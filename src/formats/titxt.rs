@@ -0,0 +1,88 @@
+use std::io::{self, BufRead, Write};
+
+use crate::formats::{parse_hex_bytes, LoadError};
+use crate::image::MemoryImage;
+
+const BYTES_PER_LINE: usize = 16;
+
+// Loads TI-TXT sections, each one an `@ADDR` line followed by lines of bytes, up to the final `q`
+pub fn read<R: BufRead>(reader: &mut R) -> Result<MemoryImage, LoadError> {
+    let mut image = MemoryImage::default();
+    let mut address: Option<u32> = None;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let error = |message: &str| LoadError::Record {
+            line: index + 1,
+            message: message.to_string(),
+        };
+
+        let content = line.trim();
+        if content.is_empty() {
+            continue;
+        }
+        if content.eq_ignore_ascii_case("q") {
+            break;
+        }
+        if let Some(digits) = content.strip_prefix('@') {
+            address = Some(
+                u32::from_str_radix(digits, 16).map_err(|_| error("invalid section address"))?,
+            );
+            continue;
+        }
+
+        let current =
+            address.ok_or_else(|| error("data found before the first section address"))?;
+        let data = content
+            .split_whitespace()
+            .map(parse_hex_bytes)
+            .collect::<Result<Vec<Vec<u8>>, &str>>()
+            .map_err(error)?
+            .concat();
+        if current + data.len() as u32 > 0x10000 {
+            return Err(error("the data is outside of the 16 bits address space"));
+        }
+        image.insert(current as u16, &data).map_err(error)?;
+        address = Some(current + data.len() as u32);
+    }
+    Ok(image)
+}
+
+pub fn write<W: Write>(image: &MemoryImage, writer: &mut W) -> io::Result<()> {
+    for segment in image.segments() {
+        writeln!(writer, "@{:04X}", segment.start)?;
+        for chunk in segment.data.chunks(BYTES_PER_LINE) {
+            let line: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+            writeln!(writer, "{}", line.join(" "))?;
+        }
+    }
+    writeln!(writer, "q")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_sections() {
+        let image = read(&mut "@C000\n31 40 00 44\n@FFFE\n00 C0\nq\n".as_bytes()).unwrap();
+        assert_eq!(image.segments().len(), 2);
+        assert_eq!(image.segments()[0].data, vec![0x31, 0x40, 0x00, 0x44]);
+        assert_eq!(image.segments()[1].start, 0xfffe);
+    }
+
+    #[test]
+    fn rejects_invalid_bytes() {
+        let result = read(&mut "@C000\n31 4G\nq\n".as_bytes());
+        assert!(matches!(result, Err(LoadError::Record { line: 2, .. })));
+    }
+
+    #[test]
+    fn written_sections_read_back() {
+        let mut image = MemoryImage::from_bytes(0xc000, (0..40).collect());
+        image.insert(0xfffe, &[0x00, 0xc0]).unwrap();
+        let mut written = Vec::new();
+        write(&image, &mut written).unwrap();
+        assert_eq!(read(&mut written.as_slice()).unwrap(), image);
+    }
+}
@@ -0,0 +1,234 @@
+use itertools::Itertools;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+// Formats without permissions describe memory that may hold anything
+impl Default for Permissions {
+    fn default() -> Self {
+        Permissions {
+            read: true,
+            write: true,
+            execute: true,
+        }
+    }
+}
+
+// Contiguous run of bytes starting at `start`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub start: u16,
+    pub data: Vec<u8>,
+    pub permissions: Permissions,
+}
+
+impl Segment {
+    // Address following the last byte, 0x10000 for a segment reaching the top of memory
+    pub fn end(&self) -> u32 {
+        u32::from(self.start) + self.data.len() as u32
+    }
+}
+
+// Memory contents loaded from a file, with gaps between the parts it does not describe
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryImage {
+    // Sorted by address, never overlapping nor adjacent with the same permissions
+    segments: Vec<Segment>,
+}
+
+impl MemoryImage {
+    pub fn from_bytes(start: u16, data: Vec<u8>) -> Self {
+        let mut image = Self::default();
+        if !data.is_empty() {
+            image.segments.push(Segment {
+                start,
+                data,
+                permissions: Permissions::default(),
+            });
+        }
+        image
+    }
+
+    // Adds `data` at `address`, merging it with the segments it touches
+    pub fn insert(&mut self, address: u16, data: &[u8]) -> Result<(), &'static str> {
+        self.insert_with(address, data, Permissions::default())
+    }
+
+    pub fn insert_with(
+        &mut self,
+        address: u16,
+        data: &[u8],
+        permissions: Permissions,
+    ) -> Result<(), &'static str> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let start = u32::from(address);
+        let end = start + data.len() as u32;
+        if end > 0x10000 {
+            return Err("the data goes past the end of the address space");
+        }
+        if self
+            .segments
+            .iter()
+            .any(|segment| start < segment.end() && u32::from(segment.start) < end)
+        {
+            return Err("the data overlaps with data loaded before");
+        }
+
+        let position = self
+            .segments
+            .iter()
+            .position(|segment| u32::from(segment.start) > start)
+            .unwrap_or(self.segments.len());
+        self.segments.insert(
+            position,
+            Segment {
+                start: address,
+                data: data.to_vec(),
+                permissions,
+            },
+        );
+
+        // Only the new segment can be adjacent to its neighbours
+        let mergeable = |first: &Segment, second: &Segment| {
+            first.end() == u32::from(second.start) && first.permissions == second.permissions
+        };
+        if position + 1 < self.segments.len()
+            && mergeable(&self.segments[position], &self.segments[position + 1])
+        {
+            let next = self.segments.remove(position + 1);
+            self.segments[position].data.extend(next.data);
+        }
+        if position > 0 && mergeable(&self.segments[position - 1], &self.segments[position]) {
+            let current = self.segments.remove(position);
+            self.segments[position - 1].data.extend(current.data);
+        }
+        Ok(())
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    // Parts of the image falling in the given ranges, ends excluded
+    pub fn select(&self, ranges: &[(u32, u32)]) -> Self {
+        // Merged first, so that no byte is selected twice
+        let mut merged: Vec<(u32, u32)> = Vec::new();
+        for (start, end) in ranges.iter().copied().sorted() {
+            match merged.last_mut() {
+                Some((_, last_end)) if start <= *last_end => *last_end = (*last_end).max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+
+        let mut selected = Self::default();
+        for segment in self.segments.iter() {
+            for (start, end) in merged.iter() {
+                let from = (*start).max(u32::from(segment.start));
+                let to = (*end).min(segment.end());
+                if from < to {
+                    let offset = (from - u32::from(segment.start)) as usize;
+                    selected
+                        .insert_with(
+                            from as u16,
+                            &segment.data[offset..offset + (to - from) as usize],
+                            segment.permissions,
+                        )
+                        .expect("disjoint ranges of disjoint segments never overlap");
+                }
+            }
+        }
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const READ_ONLY: Permissions = Permissions {
+        read: true,
+        write: false,
+        execute: false,
+    };
+
+    #[test]
+    fn adjacent_data_is_merged() {
+        let mut image = MemoryImage::default();
+        image.insert(0xc004, &[5, 6]).unwrap();
+        image.insert(0xc000, &[1, 2]).unwrap();
+        image.insert(0xc002, &[3, 4]).unwrap();
+        assert_eq!(
+            image,
+            MemoryImage::from_bytes(0xc000, vec![1, 2, 3, 4, 5, 6])
+        );
+    }
+
+    #[test]
+    fn different_permissions_are_not_merged() {
+        let mut image = MemoryImage::default();
+        image.insert(0xc000, &[1, 2]).unwrap();
+        image.insert_with(0xc002, &[3, 4], READ_ONLY).unwrap();
+        assert_eq!(image.segments().len(), 2);
+        assert_eq!(image.segments()[1].permissions, READ_ONLY);
+    }
+
+    #[test]
+    fn gaps_are_kept() {
+        let mut image = MemoryImage::default();
+        image.insert(0xfffe, &[0x00, 0xc0]).unwrap();
+        image.insert(0xc000, &[1, 2]).unwrap();
+        let starts: Vec<u16> = image
+            .segments()
+            .iter()
+            .map(|segment| segment.start)
+            .collect();
+        assert_eq!(starts, vec![0xc000, 0xfffe]);
+        assert_eq!(image.segments()[1].end(), 0x10000);
+    }
+
+    #[test]
+    fn overlaps_are_rejected() {
+        let mut image = MemoryImage::from_bytes(0xc000, vec![0; 4]);
+        assert!(image.insert(0xc003, &[1, 2]).is_err());
+        assert!(image.insert(0xbfff, &[1, 2]).is_err());
+        assert!(image.insert(0xbffe, &[1, 2]).is_ok());
+    }
+
+    #[test]
+    fn data_past_the_address_space_is_rejected() {
+        let mut image = MemoryImage::default();
+        assert!(image.insert(0xffff, &[1, 2]).is_err());
+        assert!(image.insert(0xffff, &[1]).is_ok());
+        assert!(image.insert(0x1000, &[]).is_ok());
+        assert_eq!(image.segments().len(), 1);
+    }
+
+    #[test]
+    fn selection_clips_segments() {
+        let mut image = MemoryImage::from_bytes(0xc000, (0..16).collect());
+        image.insert_with(0xfffe, &[0x00, 0xc0], READ_ONLY).unwrap();
+        let selected = image.select(&[(0xc004, 0xc008), (0xfff0, 0x10000)]);
+        assert_eq!(selected.segments().len(), 2);
+        assert_eq!(selected.segments()[0].start, 0xc004);
+        assert_eq!(selected.segments()[0].data, vec![4, 5, 6, 7]);
+        assert_eq!(selected.segments()[1].permissions, READ_ONLY);
+    }
+
+    #[test]
+    fn selection_merges_overlapping_ranges() {
+        let image = MemoryImage::from_bytes(0xc000, (0..16).collect());
+        let selected = image.select(&[(0xc006, 0xc00a), (0xc002, 0xc008), (0xc00a, 0xc00c)]);
+        assert_eq!(selected, MemoryImage::from_bytes(0xc002, (2..12).collect()));
+        assert!(image.select(&[(0x1000, 0x2000)]).is_empty());
+    }
+}
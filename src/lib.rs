@@ -0,0 +1,7 @@
+pub mod assembler;
+pub mod disassembler;
+pub mod formats;
+pub mod image;
+pub mod utils;
+
+pub use utils::{decode, decode_at, instruction_words, DecodeError, Instruction};
@@ -1,13 +1,18 @@
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::prelude::*;
-use std::io::BufReader;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
 use std::num::ParseIntError;
 use std::path::PathBuf;
 
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use itertools::Itertools;
 
-mod disassembler;
-mod utils;
+use rusty_probe::formats::elf::{self, SymbolKind};
+use rusty_probe::formats::{self, ihex, microcorruption, srec, titxt, InputFormat};
+use rusty_probe::image::MemoryImage;
+use rusty_probe::utils::device::{Device, BUNDLED_DEVICES};
+use rusty_probe::utils::peripherals::RegisterMap;
+use rusty_probe::{assembler, disassembler};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -16,21 +21,21 @@ struct Cli {
     #[clap(subcommand)]
     mode: Mode,
 
-    /// Path to the file where to write the result, if missing it will be output to stdin
+    /// Path to the file where to write the result, if missing it will be output to stdout
     #[clap(short, long, value_parser, value_name = "OUTPUT")]
     output: Option<PathBuf>,
 
-    /// Base instruction pointer for (dis)assembly.
+    /// Base instruction pointer for (dis)assembly, 0 when missing. Images loaded from a file keep
+    /// their own addresses and only use it as an entry point
     #[clap(
         short,
         long,
-        default_value_t = 0u16,
         parse(try_from_str=from_dec_or_hex),
         value_name = "CP_BASE"
     )]
-    base_pointer: u16,
+    base_pointer: Option<u16>,
 
-    /// Do not print logs or output
+    /// Do not print warnings or progress, only the result
     #[clap(short, long, action)]
     quiet: bool,
 }
@@ -45,32 +50,93 @@ enum Mode {
 
 #[derive(Debug, Args)]
 struct AssembleConfig {
+    /// File to read, standard input when missing
     #[clap(parse(try_from_str=check_and_canonicalize), value_name = "SOURCE")]
     file_path: Option<PathBuf>,
+
+    /// Always encode the immediates written in the source with an extension word instead of using
+    /// the constant generator. The constants implied by emulated mnemonics (inc, clr, setc, ...)
+    /// keep their constant generator encoding
+    #[clap(long, value_parser, action)]
+    no_constant_generator: bool,
+
+    /// Format of the assembled program
+    #[clap(long, value_enum, default_value = "hex", value_name = "FORMAT")]
+    output_format: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Bytes in memory order as a single hexadecimal string
+    Hex,
+    /// TI-TXT, as used by MSP430Flasher and the BSL tools
+    TiTxt,
+    /// Motorola S-records with 16 bits addresses
+    Srec,
 }
 
 #[derive(Debug, Args)]
 struct DisassembleConfig {
+    /// File to read, standard input when missing
     #[clap(parse(try_from_str=check_and_canonicalize), value_name = "SOURCE")]
     file_path: Option<PathBuf>,
 
+    /// Initial stack pointer, the stack grows downwards from it
     #[clap(long, parse(try_from_str=from_dec_or_hex), requires = "stack", value_name = "SP_BASE")]
     stack_begin: Option<u16>,
 
+    /// Lowest address of the stack
     #[clap(long, parse(try_from_str=from_dec_or_hex), group = "stack", value_name = "SP_END")]
     stack_end: Option<u16>,
 
+    /// Size of the stack in bytes
     #[clap(long, parse(try_from_str=from_dec_or_hex), group = "stack", value_name = "SP_SIZE")]
     stack_size: Option<u16>,
 
+    /// Do not list the interrupt vector table at 0xFFE0-0xFFFF nor follow its vectors
     #[clap(long, value_parser, action)]
     ignore_ivt: bool,
 
+    /// Bundled part (msp430f1611, msp430f2013, msp430g2553) or path to a TOML device description
+    #[clap(long, value_name = "DEVICE")]
+    device: Option<String>,
+
+    /// Do not name the special function registers (IE1, IFG1, ...)
     #[clap(long, value_parser, action)]
     ignore_special_functions: bool,
 
+    /// Do not name the peripheral registers (WDTCTL, P1OUT, ...)
     #[clap(long, value_parser, action)]
     ignore_peripherals: bool,
+
+    /// Format of the input (binary, ihex, ti-txt, srec, elf or microcorruption), detected from
+    /// its contents when missing
+    #[clap(long, value_name = "FORMAT")]
+    format: Option<InputFormat>,
+
+    /// Also disassemble the sections of ELF files that are not executable
+    #[clap(long, value_parser, action)]
+    all_sections: bool,
+
+    /// Pad an odd trailing byte with 0x00 and decode it as a full word
+    #[clap(long, value_parser, action)]
+    pad: bool,
+
+    /// Do not prefix listing lines with their address
+    #[clap(long, value_parser, action)]
+    no_address: bool,
+
+    /// Do not list the raw bytes of each instruction
+    #[clap(long, value_parser, action)]
+    no_raw_bytes: bool,
+
+    /// Only disassemble code reachable from the entry points, list everything else as data
+    #[clap(short, long, value_parser, action)]
+    recursive: bool,
+
+    /// Additional entry point for the recursive mode, can be repeated
+    #[clap(long = "entry", parse(try_from_str=from_dec_or_hex), requires = "recursive", value_name = "ADDRESS")]
+    entry_points: Vec<u16>,
 }
 
 fn check_and_canonicalize(s: &str) -> std::io::Result<PathBuf> {
@@ -86,22 +152,189 @@ fn from_dec_or_hex(s: &str) -> Result<u16, ParseIntError> {
     }
 }
 
+// Standard input stands in for a missing source, so that both modes can be used in pipelines
+fn open_source(path: Option<PathBuf>) -> Box<dyn BufRead> {
+    match path {
+        Some(path) => Box::new(BufReader::new(File::open(&path).unwrap_or_else(|error| {
+            eprintln!("Cannot open {}: {}", path.display(), error);
+            std::process::exit(1);
+        }))),
+        None => Box::new(BufReader::new(io::stdin())),
+    }
+}
+
 fn main() {
     let user_configs = Cli::parse();
+    let base_pointer = user_configs.base_pointer.unwrap_or(0);
+
+    let mut writer: Box<dyn Write> = match &user_configs.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path).unwrap_or_else(|error| {
+            eprintln!("Cannot create {}: {}", path.display(), error);
+            std::process::exit(1);
+        }))),
+        None => Box::new(io::stdout()),
+    };
 
-    if let Mode::Disassemble(config) = user_configs.mode {
-        if let Some(input) = config.file_path {
-            let f = File::open(input).unwrap();
-            let mut reader = BufReader::new(f);
-
-            let disassembled = disassembler::disassemble(
-                &mut reader,
-                user_configs.base_pointer,
-                user_configs.quiet,
-            )
-            .unwrap();
-        };
+    match user_configs.mode {
+        Mode::Assemble(config) => {
+            let mut reader = open_source(config.file_path);
+            match assembler::assemble(&mut reader, base_pointer, !config.no_constant_generator) {
+                Ok(assembled) => {
+                    match config.output_format {
+                        OutputFormat::Hex => {
+                            writeln!(writer, "{}", assembler::to_hex_string(&assembled))
+                        }
+                        OutputFormat::TiTxt | OutputFormat::Srec => {
+                            let image = MemoryImage::from_bytes(
+                                base_pointer,
+                                assembled
+                                    .iter()
+                                    .flat_map(|word| word.to_le_bytes())
+                                    .collect(),
+                            );
+                            if config.output_format == OutputFormat::TiTxt {
+                                titxt::write(&image, &mut writer)
+                            } else {
+                                srec::write(&image, &mut writer)
+                            }
+                        }
+                    }
+                    .and_then(|_| writer.flush())
+                    .unwrap_or_else(|error| {
+                        eprintln!("{}", error);
+                        std::process::exit(1);
+                    });
+                }
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Mode::Disassemble(config) => {
+            // Read whole, pipes may deliver less than the line the format is detected from
+            let mut input = Vec::new();
+            open_source(config.file_path)
+                .read_to_end(&mut input)
+                .unwrap_or_else(|error| {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                });
+            let mut reader = input.as_slice();
+
+            // Loaded images carry their own addresses, raw binaries start at the base pointer
+            let format = config.format.unwrap_or_else(|| formats::detect(&input));
+            let mut symbols = BTreeMap::new();
+            let mut entry_points = config.entry_points;
+            let loaded = match format {
+                InputFormat::Binary => None,
+                InputFormat::IntelHex => Some(ihex::read(&mut reader)),
+                InputFormat::TiTxt => Some(titxt::read(&mut reader)),
+                InputFormat::Microcorruption => Some(microcorruption::read(&mut reader)),
+                InputFormat::Srec => Some(srec::read(&mut reader)),
+                InputFormat::Elf => Some(elf::read(&mut reader).map(|loaded| {
+                    // Functions take precedence over objects at the same address
+                    for symbol in loaded.symbols.iter().sorted_by_key(|symbol| {
+                        (symbol.kind == SymbolKind::Function, symbol.name.clone())
+                    }) {
+                        symbols.insert(symbol.address, symbol.name.clone());
+                        if symbol.kind == SymbolKind::Function {
+                            entry_points.push(symbol.address);
+                        }
+                    }
+                    if config.all_sections {
+                        loaded.image
+                    } else {
+                        // The vector table is not code, but it is listed and followed all the same
+                        let mut ranges = loaded.executable;
+                        ranges.push((u32::from(disassembler::INTERRUPT_VECTORS), 0x10000));
+                        loaded.image.select(&ranges)
+                    }
+                })),
+            };
+            let image = loaded.map(|result| {
+                result.unwrap_or_else(|error| {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                })
+            });
+
+            let device = config.device.map(|name| {
+                Device::load(&name).unwrap_or_else(|error| {
+                    eprintln!("{}", error);
+                    eprintln!(
+                        "Bundled devices: {}",
+                        BUNDLED_DEVICES
+                            .iter()
+                            .map(|(name, _)| *name)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                    std::process::exit(1);
+                })
+            });
+            let registers = match &device {
+                Some(device) => RegisterMap::from_device(
+                    device,
+                    !config.ignore_special_functions,
+                    !config.ignore_peripherals,
+                ),
+                None => RegisterMap::builtin(
+                    !config.ignore_special_functions,
+                    !config.ignore_peripherals,
+                ),
+            };
+
+            let stack = match (config.stack_begin, config.stack_end, config.stack_size) {
+                (None, None, None) => None,
+                (begin, end, size) => Some(
+                    disassembler::StackRegion::new(
+                        begin.unwrap_or_else(|| {
+                            eprintln!("The stack region requires --stack-begin");
+                            std::process::exit(1);
+                        }),
+                        end,
+                        size,
+                    )
+                    .unwrap_or_else(|error| {
+                        eprintln!("Invalid stack region: {}", error);
+                        std::process::exit(1);
+                    }),
+                ),
+            };
+
+            if image.is_some() {
+                entry_points.extend(user_configs.base_pointer);
+            }
+
+            let options = disassembler::DisassemblyOptions {
+                pc_base: base_pointer,
+                quiet: user_configs.quiet,
+                pad: config.pad,
+                layout: disassembler::ListingLayout {
+                    address: !config.no_address,
+                    raw_bytes: !config.no_raw_bytes,
+                },
+                recursive: config.recursive,
+                entry_points,
+                ignore_ivt: config.ignore_ivt,
+                registers,
+                device,
+                stack,
+                symbols,
+            };
+            match image {
+                Some(image) => disassembler::disassemble_image(&image, &mut writer, &options),
+                None => disassembler::disassemble(&mut reader, &mut writer, &options),
+            }
+            .unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            });
+        }
     }
 
-    println!("Done");
+    if !user_configs.quiet {
+        eprintln!("Done");
+    }
 }
@@ -1,6 +1,6 @@
-use std::convert::{From, TryFrom};
+use std::error::Error;
 use std::fmt;
-use std::ops::{Add, Shl, Shr};
+use std::ops::Shr;
 
 pub mod two_op;
 use two_op::TwoOpInstruction;
@@ -12,10 +12,197 @@ pub mod jumps;
 use jumps::JumpInstruction;
 
 pub mod data_address;
+use data_address::{AddresingMode, AsmInstruction};
 
-#[derive(Debug)]
-enum Instruction {
+pub mod peripherals;
+use peripherals::RegisterMap;
+
+pub mod device;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
     Jump(JumpInstruction),
     OneOp(OneOpInstruction),
     TwoOp(TwoOpInstruction),
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    EmptyInput,
+    InvalidOpcode {
+        word: u16,
+        address: u16,
+    },
+    Truncated {
+        word: u16,
+        address: u16,
+        needed: usize,
+        available: usize,
+    },
+}
+
+impl DecodeError {
+    // Conversions only see the raw words, the address is filled in by the decoder
+    pub fn at(self, address: u16) -> Self {
+        match self {
+            Self::EmptyInput => Self::EmptyInput,
+            Self::InvalidOpcode { word, .. } => Self::InvalidOpcode { word, address },
+            Self::Truncated {
+                word,
+                needed,
+                available,
+                ..
+            } => Self::Truncated {
+                word,
+                address,
+                needed,
+                available,
+            },
+        }
+    }
+
+    pub fn word(&self) -> Option<u16> {
+        match *self {
+            Self::EmptyInput => None,
+            Self::InvalidOpcode { word, .. } | Self::Truncated { word, .. } => Some(word),
+        }
+    }
+
+    pub fn address(&self) -> Option<u16> {
+        match *self {
+            Self::EmptyInput => None,
+            Self::InvalidOpcode { address, .. } | Self::Truncated { address, .. } => Some(address),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyInput => write!(f, "no words to decode"),
+            Self::InvalidOpcode { word, address } => {
+                write!(f, "invalid opcode {:#06x} at {:#06x}", word, address)
+            }
+            Self::Truncated {
+                word,
+                address,
+                needed,
+                available,
+            } => write!(
+                f,
+                "instruction {:#06x} at {:#06x} needs {} words but only {} are available",
+                word, address, needed, available
+            ),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+// Number of words spanned by the instruction starting with `first_word`, extension words included
+pub fn instruction_words(first_word: u16) -> usize {
+    let extension = |register_bits: u16, addressing_bits: u16| match addressing_bits {
+        // Indexed, symbolic and absolute, except for the #1 constant
+        0b01 => usize::from(register_bits != 0b0011),
+        // Only `@PC+` reads an immediate from the following word
+        0b11 => usize::from(register_bits == 0b0000),
+        _ => 0,
+    };
+
+    let op_bits: u16 = first_word.shr(12);
+    match op_bits {
+        0b0010 | 0b0011 => 1,
+        0b0000 | 0b0001 => 1 + extension(first_word & 0b1111u16, first_word.shr(4) & 0b11u16),
+        _ => {
+            1 + extension(first_word.shr(8) & 0b1111u16, first_word.shr(4) & 0b11u16)
+                + usize::from(first_word & 0b10000000u16 != 0)
+        }
+    }
+}
+
+// Decodes the instruction at the beginning of the slice, returning it with the number of words it
+// spans. Errors report addresses relative to the start of the slice.
+pub fn decode(raw_words: &[u16]) -> Result<(Instruction, usize), DecodeError> {
+    decode_at(raw_words, 0)
+}
+
+// Same as `decode`, with the first word of the slice located at `address`
+pub fn decode_at(raw_words: &[u16], address: u16) -> Result<(Instruction, usize), DecodeError> {
+    let first_word = *raw_words.first().ok_or(DecodeError::EmptyInput)?;
+
+    let jmp_bits: u16 = first_word.shr(13);
+    let one_op_bits: u16 = first_word.shr(12);
+    let decoded = if jmp_bits == 0b001 {
+        JumpInstruction::new(first_word)
+            .map(|jump_instruction| (Instruction::Jump(jump_instruction), 1))
+    } else if one_op_bits == 0b0001 || one_op_bits == 0 {
+        OneOpInstruction::new(raw_words).map(|(one_op_instruction, extra_word)| {
+            (
+                Instruction::OneOp(one_op_instruction),
+                if extra_word { 2 } else { 1 },
+            )
+        })
+    } else {
+        TwoOpInstruction::new(raw_words).map(|(two_op_instruction, extra_words)| {
+            (
+                Instruction::TwoOp(two_op_instruction),
+                1 + usize::from(extra_words),
+            )
+        })
+    };
+
+    decoded.map_err(|error| error.at(address))
+}
+
+impl Instruction {
+    // Operands paired with the address of their extension word, when they have one
+    pub fn operands_at(&self, address: u16) -> Vec<(AddresingMode, Option<u16>)> {
+        match self {
+            Self::Jump(_) => Vec::new(),
+            Self::OneOp(instruction) => {
+                vec![(instruction.data(), instruction.extension_address(address))]
+            }
+            Self::TwoOp(instruction) => {
+                let (source_address, destination_address) =
+                    instruction.extension_addresses(address);
+                vec![
+                    (instruction.source(), source_address),
+                    (instruction.destination(), destination_address),
+                ]
+            }
+        }
+    }
+}
+
+impl Instruction {
+    // Same as the `Display` output, with absolute operands named after the registers they access
+    pub fn to_string_with(&self, registers: &RegisterMap) -> String {
+        match self {
+            Self::Jump(instruction) => instruction.to_string(),
+            Self::OneOp(instruction) => instruction.to_string_with(registers),
+            Self::TwoOp(instruction) => {
+                if let Some(emulated) = instruction.emulated_form() {
+                    emulated.to_string_with(registers)
+                } else {
+                    instruction.to_string_with(registers)
+                }
+            }
+        }
+    }
+}
+
+impl AsmInstruction for Instruction {
+    fn encode(&self) -> Vec<u16> {
+        match self {
+            Self::Jump(instruction) => instruction.encode(),
+            Self::OneOp(instruction) => instruction.encode(),
+            Self::TwoOp(instruction) => instruction.encode(),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_string_with(&RegisterMap::default()))
+    }
+}
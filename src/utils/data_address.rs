@@ -1,4 +1,8 @@
 use std::fmt;
+use std::str::FromStr;
+
+use crate::utils::peripherals::RegisterMap;
+use crate::utils::{instruction_words, DecodeError};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Register {
@@ -43,7 +47,7 @@ impl TryFrom<u16> for Register {
             0b1101 => Ok(Self::R13),
             0b1110 => Ok(Self::R14),
             0b1111 => Ok(Self::R15),
-            _ => Err("inexisting register"),
+            _ => Err("the provided value is not a register identifier"),
         }
     }
 }
@@ -71,6 +75,31 @@ impl From<Register> for u16 {
     }
 }
 
+impl FromStr for Register {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pc" | "r0" => Ok(Self::Pc),
+            "sp" | "r1" => Ok(Self::Sp),
+            "sr" | "r2" => Ok(Self::Sr),
+            "cg" | "r3" => Ok(Self::Cg),
+            "r4" => Ok(Self::R4),
+            "r5" => Ok(Self::R5),
+            "r6" => Ok(Self::R6),
+            "r7" => Ok(Self::R7),
+            "r8" => Ok(Self::R8),
+            "r9" => Ok(Self::R9),
+            "r10" => Ok(Self::R10),
+            "r11" => Ok(Self::R11),
+            "r12" => Ok(Self::R12),
+            "r13" => Ok(Self::R13),
+            "r14" => Ok(Self::R14),
+            "r15" => Ok(Self::R15),
+            _ => Err("inexisting register"),
+        }
+    }
+}
+
 impl From<Register> for String {
     fn from(val: Register) -> Self {
         match val {
@@ -155,16 +184,29 @@ impl fmt::Display for DataMode {
     }
 }
 
-pub trait AsmInstruction {}
+pub trait AsmInstruction {
+    fn encode(&self) -> Vec<u16>;
+}
+
+pub(crate) fn extension_word(raw_words: &[u16], index: usize) -> Result<u16, DecodeError> {
+    let first_word = *raw_words.first().ok_or(DecodeError::EmptyInput)?;
+    raw_words.get(index).copied().ok_or(DecodeError::Truncated {
+        word: first_word,
+        address: 0,
+        needed: instruction_words(first_word),
+        available: raw_words.len(),
+    })
+}
 
 pub fn parse_address(
     register: Register,
     addr_bits: u16,
     raw_words: &[u16],
-) -> Result<(AddresingMode, bool), &'static str> {
+) -> Result<(AddresingMode, bool), DecodeError> {
     let mut extra_word_used = false;
 
-    let mode = match addr_bits {
+    // Only the two lowest bits select the addressing mode
+    let mode = match addr_bits & 0b11 {
         0b00 => {
             if register == Register::Cg {
                 AddresingMode::Immediate(0)
@@ -175,16 +217,16 @@ pub fn parse_address(
         0b01 => match register {
             Register::Pc => {
                 extra_word_used = true;
-                AddresingMode::Symbolic(raw_words[1])
+                AddresingMode::Symbolic(extension_word(raw_words, 1)?)
             }
             Register::Sr => {
                 extra_word_used = true;
-                AddresingMode::Absolute(raw_words[1])
+                AddresingMode::Absolute(extension_word(raw_words, 1)?)
             }
             Register::Cg => AddresingMode::Immediate(1),
             _ => {
                 extra_word_used = true;
-                AddresingMode::Indexed((raw_words[1], register))
+                AddresingMode::Indexed((extension_word(raw_words, 1)?, register))
             }
         },
         0b10 => match register {
@@ -192,28 +234,121 @@ pub fn parse_address(
             Register::Cg => AddresingMode::Immediate(2),
             _ => AddresingMode::Indirect(register),
         },
-        0b11 => match register {
+        _ => match register {
             Register::Pc => {
                 extra_word_used = true;
-                AddresingMode::Immediate(raw_words[1])
+                AddresingMode::Immediate(extension_word(raw_words, 1)?)
             }
             Register::Sr => AddresingMode::Immediate(8),
             Register::Cg => AddresingMode::Immediate(-1i16 as u16),
             _ => AddresingMode::Autoincrement(register),
         },
-        _ => return Err("provided an invalid addressing mode value"),
     };
 
     Ok((mode, extra_word_used))
 }
 
+// Returns the register and the As bits generating the value without an extension word
+pub fn constant_generator(value: u16) -> Option<(Register, u16)> {
+    match value {
+        0 => Some((Register::Cg, 0b00)),
+        1 => Some((Register::Cg, 0b01)),
+        2 => Some((Register::Cg, 0b10)),
+        4 => Some((Register::Sr, 0b10)),
+        8 => Some((Register::Sr, 0b11)),
+        0xffff => Some((Register::Cg, 0b11)),
+        _ => None,
+    }
+}
+
+// Whether a decoded source was encoded with the constant generator enabled, which only shows for
+// constants written in the long form although they could have been generated
+pub fn uses_constant_generator(mode: AddresingMode, extension_word_used: bool) -> bool {
+    match mode {
+        AddresingMode::Immediate(value) if extension_word_used => {
+            constant_generator(value).is_none()
+        }
+        _ => true,
+    }
+}
+
+// Returns the register, the As bits and the optional extension word encoding a source operand
+pub fn encode_source(mode: AddresingMode, use_constants: bool) -> (Register, u16, Option<u16>) {
+    if let AddresingMode::Immediate(value) = mode {
+        if let Some((register, addressing_bits)) =
+            constant_generator(value).filter(|_| use_constants)
+        {
+            return (register, addressing_bits, None);
+        }
+    }
+
+    match mode {
+        AddresingMode::Direct(register) => (register, 0b00, None),
+        AddresingMode::Indexed((offset, register)) => (register, 0b01, Some(offset)),
+        AddresingMode::Indirect(register) => (register, 0b10, None),
+        AddresingMode::Autoincrement(register) => (register, 0b11, None),
+        AddresingMode::Absolute(address) => (Register::Sr, 0b01, Some(address)),
+        AddresingMode::Symbolic(offset) => (Register::Pc, 0b01, Some(offset)),
+        AddresingMode::Immediate(value) => (Register::Pc, 0b11, Some(value)),
+    }
+}
+
+// Returns the register, the Ad bit and the optional extension word encoding a destination operand
+pub fn encode_destination(
+    mode: AddresingMode,
+) -> Result<(Register, u16, Option<u16>), &'static str> {
+    match mode {
+        AddresingMode::Direct(register) => Ok((register, 0, None)),
+        AddresingMode::Indexed((offset, register)) => Ok((register, 1, Some(offset))),
+        AddresingMode::Absolute(address) => Ok((Register::Sr, 1, Some(address))),
+        AddresingMode::Symbolic(offset) => Ok((Register::Pc, 1, Some(offset))),
+        _ => Err("the addressing mode cannot be used for a destination operand"),
+    }
+}
+
+// Address accessed by the operand, when it doesn't depend on the content of a register
+pub fn effective_address(mode: AddresingMode, extension_address: Option<u16>) -> Option<u16> {
+    match mode {
+        AddresingMode::Absolute(address) => Some(address),
+        AddresingMode::Symbolic(offset) => extension_address.map(|base| base.wrapping_add(offset)),
+        _ => None,
+    }
+}
+
 pub fn get_signed_hex(src: u16) -> String {
     let signed = src as i16;
-    let abs = signed.abs() as u16;
+    let abs = signed.unsigned_abs();
     let sign = if signed < 0 { "-" } else { "" };
     format!("{}{:#x}", sign, abs)
 }
 
+impl AddresingMode {
+    pub fn named(self, registers: &RegisterMap) -> NamedOperand<'_> {
+        NamedOperand {
+            mode: self,
+            registers,
+        }
+    }
+}
+
+// Operand printed with the name of the register it accesses, when it has one
+pub struct NamedOperand<'a> {
+    mode: AddresingMode,
+    registers: &'a RegisterMap,
+}
+
+impl fmt::Display for NamedOperand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            AddresingMode::Absolute(address) => match self.registers.name(address) {
+                Some(name) => write!(f, "&{}", name),
+                None => write!(f, "{}", self.mode),
+            },
+            _ => write!(f, "{}", self.mode),
+        }
+    }
+}
+
 impl fmt::Display for AddresingMode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
@@ -224,7 +359,7 @@ impl fmt::Display for AddresingMode {
             Self::Indirect(reg) => write!(f, "@{}", String::from(reg)),
             Self::Autoincrement(reg) => write!(f, "@{}+", String::from(reg)),
             Self::Absolute(address) => write!(f, "&{:#x}", address),
-            Self::Symbolic(offset) => write!(f, "{}", get_signed_hex(offset)),
+            Self::Symbolic(offset) => write!(f, "{}(PC)", get_signed_hex(offset)),
             Self::Immediate(value) => write!(f, "#{:#x} ({})", value, value as i16),
         }
     }
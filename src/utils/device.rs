@@ -0,0 +1,175 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::utils::peripherals::{Bitfield, RegisterClass};
+
+// Descriptions shipped with the binary, selected by name with `--device`
+pub const BUNDLED_DEVICES: &[(&str, &str)] = &[
+    (
+        "msp430f1611",
+        include_str!("../../devices/msp430f1611.toml"),
+    ),
+    (
+        "msp430f2013",
+        include_str!("../../devices/msp430f2013.toml"),
+    ),
+    (
+        "msp430g2553",
+        include_str!("../../devices/msp430g2553.toml"),
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MemoryKind {
+    Peripherals,
+    Ram,
+    Info,
+    Flash,
+}
+
+impl fmt::Display for MemoryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::Peripherals => "peripherals",
+            Self::Ram => "ram",
+            Self::Info => "info",
+            Self::Flash => "flash",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+// Inclusive range of addresses
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct MemoryRange {
+    pub name: String,
+    pub kind: MemoryKind,
+    pub start: u16,
+    pub end: u16,
+}
+
+impl MemoryRange {
+    pub fn contains(&self, address: u16) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RegisterDescription {
+    pub name: String,
+    pub address: u16,
+    pub class: RegisterClass,
+    #[serde(default)]
+    pub bitfields: Vec<Bitfield>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct VectorDescription {
+    pub name: String,
+    pub address: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Device {
+    pub name: String,
+    #[serde(default)]
+    pub memory: Vec<MemoryRange>,
+    #[serde(default)]
+    pub registers: Vec<RegisterDescription>,
+    #[serde(default)]
+    pub vectors: Vec<VectorDescription>,
+}
+
+#[derive(Debug)]
+pub enum DeviceError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "cannot read the device description: {}", error),
+            Self::Parse(error) => write!(f, "invalid device description: {}", error),
+        }
+    }
+}
+
+impl Error for DeviceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Parse(error) => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for DeviceError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<toml::de::Error> for DeviceError {
+    fn from(error: toml::de::Error) -> Self {
+        Self::Parse(error)
+    }
+}
+
+impl FromStr for Device {
+    type Err = DeviceError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(toml::from_str(s)?)
+    }
+}
+
+impl Device {
+    // Looks for a bundled part first, then for a description file at that path
+    pub fn load(name_or_path: &str) -> Result<Self, DeviceError> {
+        match BUNDLED_DEVICES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(name_or_path))
+        {
+            Some((_, description)) => description.parse(),
+            None => fs::read_to_string(name_or_path)?.parse(),
+        }
+    }
+
+    pub fn vector_name(&self, address: u16) -> Option<&str> {
+        self.vectors
+            .iter()
+            .find(|vector| vector.address == address)
+            .map(|vector| vector.name.as_str())
+    }
+
+    pub fn memory_range(&self, address: u16) -> Option<&MemoryRange> {
+        self.memory.iter().find(|range| range.contains(address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_devices_load() {
+        for (name, _) in BUNDLED_DEVICES {
+            let device = Device::load(name).unwrap_or_else(|error| panic!("{}: {}", name, error));
+            assert!(device.name.eq_ignore_ascii_case(name), "{}", name);
+            assert!(!device.memory.is_empty(), "{}", name);
+            assert!(!device.registers.is_empty(), "{}", name);
+            assert_eq!(device.vector_name(0xfffe), Some("RESET"), "{}", name);
+        }
+    }
+
+    #[test]
+    fn bundled_names_ignore_case() {
+        assert!(Device::load("MSP430G2553").is_ok());
+    }
+}
@@ -1,6 +1,8 @@
 use crate::utils::data_address::{get_signed_hex, AsmInstruction};
+use crate::utils::DecodeError;
 use std::fmt;
 use std::ops::{Shl, Shr};
+use std::str::FromStr;
 
 // Jumps
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -16,11 +18,14 @@ pub enum JumpOp {
 }
 
 impl TryFrom<u16> for JumpOp {
-    type Error = &'static str;
+    type Error = DecodeError;
     fn try_from(value: u16) -> Result<Self, Self::Error> {
         let op_data: u16 = value.shr(13);
         if op_data != 0b001 {
-            return Err("the provided word is not a jump op code");
+            return Err(DecodeError::InvalidOpcode {
+                word: value,
+                address: 0,
+            });
         }
         let masked_data: u16 = value.shr(10) & 0b111u16;
         match masked_data {
@@ -32,7 +37,10 @@ impl TryFrom<u16> for JumpOp {
             0b101 => Ok(Self::Jge),
             0b110 => Ok(Self::Jl),
             0b111 => Ok(Self::Jmp),
-            _ => Err("somehow we managed to generate a value that's not a 3 bit u16"),
+            _ => Err(DecodeError::InvalidOpcode {
+                word: value,
+                address: 0,
+            }),
         }
     }
 }
@@ -54,6 +62,23 @@ impl From<JumpOp> for u16 {
     }
 }
 
+impl FromStr for JumpOp {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "jne" | "jnz" => Ok(Self::Jne),
+            "jeq" | "jz" => Ok(Self::Jeq),
+            "jlo" | "jnc" => Ok(Self::Jlo),
+            "jhs" | "jc" => Ok(Self::Jhs),
+            "jn" => Ok(Self::Jn),
+            "jge" => Ok(Self::Jge),
+            "jl" => Ok(Self::Jl),
+            "jmp" => Ok(Self::Jmp),
+            _ => Err("the provided mnemonic is not a jump operation"),
+        }
+    }
+}
+
 impl From<JumpOp> for String {
     fn from(val: JumpOp) -> Self {
         match val {
@@ -76,15 +101,15 @@ impl fmt::Display for JumpOp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct JumpInstruction {
     operation: JumpOp,
     offset: u16,
 }
 
 impl JumpInstruction {
-    pub fn new(word: u16) -> Self {
-        let operation = JumpOp::try_from(word).unwrap();
+    pub fn new(word: u16) -> Result<Self, DecodeError> {
+        let operation = JumpOp::try_from(word)?;
         let offset_bits = word & 0b1111111111u16;
         let offset = if offset_bits & 0b1000000000u16 != 0 {
             offset_bits + 0b111111u16.shl(10)
@@ -92,14 +117,51 @@ impl JumpInstruction {
             offset_bits
         };
 
-        Self { operation, offset }
+        Ok(Self { operation, offset })
+    }
+
+    pub fn operation(&self) -> JumpOp {
+        self.operation
+    }
+
+    pub fn offset(&self) -> i16 {
+        self.offset as i16
+    }
+
+    // Offsets are counted in words from the one following the jump
+    pub fn target(&self, pc: u16) -> u16 {
+        pc.wrapping_add(2).wrapping_add(self.offset.wrapping_mul(2))
+    }
+
+    // The offset is expressed in words, relative to the word following the jump
+    pub fn build(operation: JumpOp, offset: i16) -> Result<Self, &'static str> {
+        if !(-512..=511).contains(&offset) {
+            return Err("the jump offset does not fit in 10 bits");
+        }
+        Ok(Self {
+            operation,
+            offset: offset as u16,
+        })
     }
 }
 
-impl AsmInstruction for JumpInstruction {}
+impl AsmInstruction for JumpInstruction {
+    fn encode(&self) -> Vec<u16> {
+        vec![u16::from(self.operation) + (self.offset & 0b1111111111u16)]
+    }
+}
 
 impl fmt::Display for JumpInstruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} {}", self.operation, get_signed_hex(self.offset))
+        // Relative to the jump itself, as in `$+0x6`
+        let distance = self.offset.wrapping_mul(2).wrapping_add(2);
+        let sign = if (distance as i16) < 0 { "" } else { "+" };
+        write!(
+            f,
+            "{} ${}{}",
+            self.operation,
+            sign,
+            get_signed_hex(distance)
+        )
     }
 }
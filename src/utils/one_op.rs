@@ -1,8 +1,12 @@
 use crate::utils::data_address::{
-    parse_address, AddresingMode, AsmInstruction, DataMode, Register,
+    encode_source, parse_address, uses_constant_generator, AddresingMode, AsmInstruction, DataMode,
+    Register,
 };
+use crate::utils::peripherals::RegisterMap;
+use crate::utils::DecodeError;
 use std::fmt;
 use std::ops::{Shl, Shr};
+use std::str::FromStr;
 
 // Single Operand instructions (Format II)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -17,11 +21,16 @@ pub enum OneOp {
 }
 
 impl TryFrom<u16> for OneOp {
-    type Error = &'static str;
+    type Error = DecodeError;
     fn try_from(value: u16) -> Result<Self, Self::Error> {
-        let op_data: u16 = value.shr(12);
-        if op_data != 0b0001 && op_data != 0 {
-            return Err("the provided word is not a one operand op code");
+        let invalid = DecodeError::InvalidOpcode {
+            word: value,
+            address: 0,
+        };
+        // Format II instructions all start with 0b000100
+        let op_data: u16 = value.shr(10);
+        if op_data != 0b000100 {
+            return Err(invalid);
         }
         let masked_data: u16 = value.shr(7) & 0b111u16;
         match masked_data {
@@ -32,7 +41,7 @@ impl TryFrom<u16> for OneOp {
             0b100 => Ok(Self::Push),
             0b101 => Ok(Self::Call),
             0b110 => Ok(Self::Reti),
-            _ => Err("inexisting operation"),
+            _ => Err(invalid),
         }
     }
 }
@@ -53,6 +62,29 @@ impl From<OneOp> for u16 {
     }
 }
 
+impl FromStr for OneOp {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rrc" => Ok(Self::Rrc),
+            "swpb" => Ok(Self::Swpb),
+            "rra" => Ok(Self::Rra),
+            "sxt" => Ok(Self::Sxt),
+            "push" => Ok(Self::Push),
+            "call" => Ok(Self::Call),
+            "reti" => Ok(Self::Reti),
+            _ => Err("the provided mnemonic is not a one operand operation"),
+        }
+    }
+}
+
+impl OneOp {
+    // Erratum CPU4: `push #4` and `push #8` misbehave when generated from SR
+    pub fn supports_constant(&self, value: u16) -> bool {
+        !(*self == Self::Push && (value == 4 || value == 8))
+    }
+}
+
 impl From<OneOp> for String {
     fn from(val: OneOp) -> Self {
         match val {
@@ -62,7 +94,7 @@ impl From<OneOp> for String {
             OneOp::Sxt => "sxt",
             OneOp::Push => "push",
             OneOp::Call => "call",
-            OneOp::Reti => "ret",
+            OneOp::Reti => "reti",
         }
         .to_string()
     }
@@ -74,47 +106,155 @@ impl fmt::Display for OneOp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct OneOpInstruction {
     operation: OneOp,
     data: AddresingMode,
     mode: Option<DataMode>,
+    constant_generator: bool,
 }
 
-impl AsmInstruction for OneOpInstruction {}
+impl AsmInstruction for OneOpInstruction {
+    fn encode(&self) -> Vec<u16> {
+        let (register, addressing_bits, extra_word) =
+            encode_source(self.data, self.constant_generator);
+        let mode_bits = self.mode.map(u16::from).unwrap_or(0);
+
+        let op_word =
+            u16::from(self.operation) + mode_bits + addressing_bits.shl(4) + u16::from(register);
+
+        [Some(op_word), extra_word].into_iter().flatten().collect()
+    }
+}
 
 impl OneOpInstruction {
-    pub fn new(raw_words: &[u16]) -> (Self, bool) {
-        let operation = OneOp::try_from(raw_words[0]).unwrap();
+    pub fn build(
+        operation: OneOp,
+        data: AddresingMode,
+        mode: Option<DataMode>,
+    ) -> Result<Self, &'static str> {
+        let byte_capable =
+            operation == OneOp::Rrc || operation == OneOp::Rra || operation == OneOp::Push;
+        if mode == Some(DataMode::Byte) && !byte_capable {
+            return Err("the operation does not support byte mode");
+        }
+        let mode = if byte_capable {
+            Some(mode.unwrap_or(DataMode::Word))
+        } else {
+            None
+        };
+        let data = if operation == OneOp::Reti {
+            AddresingMode::Direct(Register::Pc)
+        } else {
+            data
+        };
+
+        Ok(Self {
+            operation,
+            data,
+            mode,
+            constant_generator: match data {
+                AddresingMode::Immediate(value) => operation.supports_constant(value),
+                _ => true,
+            },
+        })
+    }
+
+    // Disabling the constant generator forces immediates into the long `@PC+` form
+    pub fn with_constant_generator(mut self, enabled: bool) -> Self {
+        self.constant_generator = enabled;
+        self
+    }
+
+    pub fn extension_address(&self, address: u16) -> Option<u16> {
+        encode_source(self.data, self.constant_generator)
+            .2
+            .map(|_| address.wrapping_add(2))
+    }
+
+    pub fn operation(&self) -> OneOp {
+        self.operation
+    }
+
+    pub fn data(&self) -> AddresingMode {
+        self.data
+    }
+
+    pub fn mode(&self) -> Option<DataMode> {
+        self.mode
+    }
+
+    pub fn new(raw_words: &[u16]) -> Result<(Self, bool), DecodeError> {
+        let first_word = *raw_words.first().ok_or(DecodeError::EmptyInput)?;
+        let operation = OneOp::try_from(first_word)?;
         let mode = if operation == OneOp::Rrc || operation == OneOp::Rra || operation == OneOp::Push
         {
-            Some(DataMode::from(raw_words[0]))
+            Some(DataMode::from(first_word))
         } else {
             None
         };
 
-        let data_register = Register::try_from(raw_words[0] & 0b1111u16).unwrap();
-        let addr_mode_bits = raw_words[0].shr(4) & 0b11u16;
-        let (data, word_used) = parse_address(data_register, addr_mode_bits, raw_words).unwrap();
+        let data_register = Register::try_from(first_word & 0b1111u16)
+            .expect("register identifiers are 4 bits wide");
+        let addr_mode_bits = first_word.shr(4) & 0b11u16;
+        let (data, word_used) = parse_address(data_register, addr_mode_bits, raw_words)?;
+        let constant_generator = uses_constant_generator(data, word_used);
 
-        (
+        Ok((
             Self {
                 operation,
                 data,
                 mode,
+                constant_generator,
             },
             word_used,
-        )
+        ))
     }
 }
 
-impl fmt::Display for OneOpInstruction {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl OneOpInstruction {
+    pub fn to_string_with(&self, registers: &RegisterMap) -> String {
         let mode_string = if self.mode == Some(DataMode::Byte) {
             ".b"
         } else {
             ""
         };
-        write!(f, "{}{} {}", self.operation, mode_string, self.data)
+        if self.operation == OneOp::Reti {
+            return self.operation.to_string();
+        }
+        format!(
+            "{}{} {}",
+            self.operation,
+            mode_string,
+            self.data.named(registers)
+        )
+    }
+}
+
+impl fmt::Display for OneOpInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_string_with(&RegisterMap::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{decode, Instruction};
+
+    #[test]
+    fn built_instructions_decode_to_themselves() {
+        let cases = [
+            OneOpInstruction::build(OneOp::Push, AddresingMode::Direct(Register::R5), None),
+            OneOpInstruction::build(OneOp::Push, AddresingMode::Immediate(2), None),
+            // Erratum CPU4, never generated
+            OneOpInstruction::build(OneOp::Push, AddresingMode::Immediate(4), None),
+            OneOpInstruction::build(OneOp::Call, AddresingMode::Immediate(0xc000), None),
+        ]
+        .map(Result::unwrap);
+        for instruction in cases {
+            let (decoded, _) = decode(&instruction.encode()).unwrap();
+            assert_eq!(decoded, Instruction::OneOp(instruction));
+        }
     }
 }
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::utils::device::Device;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegisterClass {
+    // Interrupt enable and flag registers at the very beginning of the address space
+    SpecialFunction,
+    Peripheral,
+}
+
+// Named value of the bits selected by `mask`, a single flag when `value` is missing
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Bitfield {
+    pub name: String,
+    pub mask: u16,
+    pub value: Option<u16>,
+}
+
+impl Bitfield {
+    fn value(&self) -> u16 {
+        self.value.unwrap_or(self.mask)
+    }
+}
+
+// Registers of the MSP430x1xx and MSP430x2xx families
+const BUILTIN_REGISTERS: &[(u16, &str, RegisterClass)] = &[
+    (0x0000, "IE1", RegisterClass::SpecialFunction),
+    (0x0001, "IE2", RegisterClass::SpecialFunction),
+    (0x0002, "IFG1", RegisterClass::SpecialFunction),
+    (0x0003, "IFG2", RegisterClass::SpecialFunction),
+    // Digital I/O
+    (0x0010, "P3REN", RegisterClass::Peripheral),
+    (0x0018, "P3IN", RegisterClass::Peripheral),
+    (0x0019, "P3OUT", RegisterClass::Peripheral),
+    (0x001a, "P3DIR", RegisterClass::Peripheral),
+    (0x001b, "P3SEL", RegisterClass::Peripheral),
+    (0x0020, "P1IN", RegisterClass::Peripheral),
+    (0x0021, "P1OUT", RegisterClass::Peripheral),
+    (0x0022, "P1DIR", RegisterClass::Peripheral),
+    (0x0023, "P1IFG", RegisterClass::Peripheral),
+    (0x0024, "P1IES", RegisterClass::Peripheral),
+    (0x0025, "P1IE", RegisterClass::Peripheral),
+    (0x0026, "P1SEL", RegisterClass::Peripheral),
+    (0x0027, "P1REN", RegisterClass::Peripheral),
+    (0x0028, "P2IN", RegisterClass::Peripheral),
+    (0x0029, "P2OUT", RegisterClass::Peripheral),
+    (0x002a, "P2DIR", RegisterClass::Peripheral),
+    (0x002b, "P2IFG", RegisterClass::Peripheral),
+    (0x002c, "P2IES", RegisterClass::Peripheral),
+    (0x002d, "P2IE", RegisterClass::Peripheral),
+    (0x002e, "P2SEL", RegisterClass::Peripheral),
+    (0x002f, "P2REN", RegisterClass::Peripheral),
+    // ADC10 data transfer and analog enable
+    (0x0048, "ADC10DTC0", RegisterClass::Peripheral),
+    (0x0049, "ADC10DTC1", RegisterClass::Peripheral),
+    (0x004a, "ADC10AE0", RegisterClass::Peripheral),
+    // Basic clock system
+    (0x0053, "BCSCTL3", RegisterClass::Peripheral),
+    (0x0056, "DCOCTL", RegisterClass::Peripheral),
+    (0x0057, "BCSCTL1", RegisterClass::Peripheral),
+    (0x0058, "BCSCTL2", RegisterClass::Peripheral),
+    // Comparator_A+
+    (0x0059, "CACTL1", RegisterClass::Peripheral),
+    (0x005a, "CACTL2", RegisterClass::Peripheral),
+    (0x005b, "CAPD", RegisterClass::Peripheral),
+    // USCI
+    (0x005d, "UCA0ABCTL", RegisterClass::Peripheral),
+    (0x005e, "UCA0IRTCTL", RegisterClass::Peripheral),
+    (0x005f, "UCA0IRRCTL", RegisterClass::Peripheral),
+    (0x0060, "UCA0CTL0", RegisterClass::Peripheral),
+    (0x0061, "UCA0CTL1", RegisterClass::Peripheral),
+    (0x0062, "UCA0BR0", RegisterClass::Peripheral),
+    (0x0063, "UCA0BR1", RegisterClass::Peripheral),
+    (0x0064, "UCA0MCTL", RegisterClass::Peripheral),
+    (0x0065, "UCA0STAT", RegisterClass::Peripheral),
+    (0x0066, "UCA0RXBUF", RegisterClass::Peripheral),
+    (0x0067, "UCA0TXBUF", RegisterClass::Peripheral),
+    (0x0068, "UCB0CTL0", RegisterClass::Peripheral),
+    (0x0069, "UCB0CTL1", RegisterClass::Peripheral),
+    (0x006a, "UCB0BR0", RegisterClass::Peripheral),
+    (0x006b, "UCB0BR1", RegisterClass::Peripheral),
+    (0x006d, "UCB0STAT", RegisterClass::Peripheral),
+    (0x006e, "UCB0RXBUF", RegisterClass::Peripheral),
+    (0x006f, "UCB0TXBUF", RegisterClass::Peripheral),
+    // Watchdog and flash controller
+    (0x0120, "WDTCTL", RegisterClass::Peripheral),
+    (0x0128, "FCTL1", RegisterClass::Peripheral),
+    (0x012a, "FCTL2", RegisterClass::Peripheral),
+    (0x012c, "FCTL3", RegisterClass::Peripheral),
+    // Hardware multiplier
+    (0x0130, "MPY", RegisterClass::Peripheral),
+    (0x0132, "MPYS", RegisterClass::Peripheral),
+    (0x0134, "MAC", RegisterClass::Peripheral),
+    (0x0136, "MACS", RegisterClass::Peripheral),
+    (0x0138, "OP2", RegisterClass::Peripheral),
+    (0x013a, "RESLO", RegisterClass::Peripheral),
+    (0x013c, "RESHI", RegisterClass::Peripheral),
+    (0x013e, "SUMEXT", RegisterClass::Peripheral),
+    // Timer0_A3 and Timer1_A3
+    (0x011e, "TA1IV", RegisterClass::Peripheral),
+    (0x012e, "TA0IV", RegisterClass::Peripheral),
+    (0x0160, "TA0CTL", RegisterClass::Peripheral),
+    (0x0162, "TA0CCTL0", RegisterClass::Peripheral),
+    (0x0164, "TA0CCTL1", RegisterClass::Peripheral),
+    (0x0166, "TA0CCTL2", RegisterClass::Peripheral),
+    (0x0170, "TA0R", RegisterClass::Peripheral),
+    (0x0172, "TA0CCR0", RegisterClass::Peripheral),
+    (0x0174, "TA0CCR1", RegisterClass::Peripheral),
+    (0x0176, "TA0CCR2", RegisterClass::Peripheral),
+    (0x0180, "TA1CTL", RegisterClass::Peripheral),
+    (0x0182, "TA1CCTL0", RegisterClass::Peripheral),
+    (0x0184, "TA1CCTL1", RegisterClass::Peripheral),
+    (0x0186, "TA1CCTL2", RegisterClass::Peripheral),
+    (0x0190, "TA1R", RegisterClass::Peripheral),
+    (0x0192, "TA1CCR0", RegisterClass::Peripheral),
+    (0x0194, "TA1CCR1", RegisterClass::Peripheral),
+    (0x0196, "TA1CCR2", RegisterClass::Peripheral),
+    // ADC10
+    (0x01b0, "ADC10CTL0", RegisterClass::Peripheral),
+    (0x01b2, "ADC10CTL1", RegisterClass::Peripheral),
+    (0x01b4, "ADC10MEM", RegisterClass::Peripheral),
+    (0x01bc, "ADC10SA", RegisterClass::Peripheral),
+];
+
+// Names of the memory mapped registers, used to print absolute operands as `&WDTCTL`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegisterMap {
+    names: HashMap<u16, String>,
+    bitfields: HashMap<u16, Vec<Bitfield>>,
+}
+
+impl RegisterMap {
+    pub fn builtin(special_functions: bool, peripherals: bool) -> Self {
+        let mut registers = Self::default();
+        for (address, name, class) in BUILTIN_REGISTERS {
+            let enabled = match class {
+                RegisterClass::SpecialFunction => special_functions,
+                RegisterClass::Peripheral => peripherals,
+            };
+            if enabled {
+                registers.insert(*address, name);
+            }
+        }
+        registers
+    }
+
+    pub fn from_device(device: &Device, special_functions: bool, peripherals: bool) -> Self {
+        let mut registers = Self::default();
+        for register in device.registers.iter() {
+            let enabled = match register.class {
+                RegisterClass::SpecialFunction => special_functions,
+                RegisterClass::Peripheral => peripherals,
+            };
+            if enabled {
+                registers.insert(register.address, &register.name);
+                if !register.bitfields.is_empty() {
+                    registers
+                        .bitfields
+                        .insert(register.address, register.bitfields.clone());
+                }
+            }
+        }
+        registers
+    }
+
+    pub fn insert(&mut self, address: u16, name: &str) {
+        self.names.insert(address, name.to_string());
+    }
+
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(String::as_str)
+    }
+
+    // Spells `value` as the bitfields of the register at `address`, as in `WDTPW|WDTHOLD`
+    pub fn describe_bits(&self, address: u16, value: u16) -> Option<String> {
+        let bitfields = self.bitfields.get(&address)?;
+        let mut covered = 0u16;
+        let mut names = Vec::new();
+        // Cleared fields would match any value, so only the set ones are spelled out
+        for bitfield in bitfields.iter().filter(|bitfield| bitfield.value() != 0) {
+            if value & bitfield.mask == bitfield.value() {
+                covered |= bitfield.mask;
+                names.push(bitfield.name.clone());
+            }
+        }
+        if names.is_empty() {
+            return None;
+        }
+        if value & !covered != 0 {
+            names.push(format!("{:#x}", value & !covered));
+        }
+        Some(names.into_iter().join("|"))
+    }
+}
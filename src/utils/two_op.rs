@@ -1,8 +1,12 @@
 use crate::utils::data_address::{
-    parse_address, AddresingMode, AsmInstruction, DataMode, Register,
+    encode_destination, encode_source, extension_word, parse_address, uses_constant_generator,
+    AddresingMode, AsmInstruction, DataMode, Register,
 };
+use crate::utils::peripherals::RegisterMap;
+use crate::utils::DecodeError;
 use std::fmt;
 use std::ops::{Shl, Shr};
+use std::str::FromStr;
 
 // Double operands instructions (Format I)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -22,12 +26,9 @@ pub enum TwoOp {
 }
 
 impl TryFrom<u16> for TwoOp {
-    type Error = String;
+    type Error = DecodeError;
     fn try_from(value: u16) -> Result<Self, Self::Error> {
         let op_data: u16 = value.shr(12);
-        if op_data == 0b0001 {
-            return Err("the provided word is not a two operand op code".to_string());
-        }
         match op_data {
             0b0100 => Ok(Self::Mov),
             0b0101 => Ok(Self::Add),
@@ -41,10 +42,10 @@ impl TryFrom<u16> for TwoOp {
             0b1101 => Ok(Self::Bis),
             0b1110 => Ok(Self::Xor),
             0b1111 => Ok(Self::And),
-            _ => Err(format!(
-                "inexisting operation, I was given: {:#b} from word: {:#x}",
-                op_data, value
-            )),
+            _ => Err(DecodeError::InvalidOpcode {
+                word: value,
+                address: 0,
+            }),
         }
     }
 }
@@ -69,6 +70,27 @@ impl From<TwoOp> for u16 {
     }
 }
 
+impl FromStr for TwoOp {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mov" => Ok(Self::Mov),
+            "add" => Ok(Self::Add),
+            "addc" => Ok(Self::Addc),
+            "subc" => Ok(Self::Subc),
+            "sub" => Ok(Self::Sub),
+            "cmp" => Ok(Self::Cmp),
+            "dadd" => Ok(Self::Dadd),
+            "bit" => Ok(Self::Bit),
+            "bic" => Ok(Self::Bic),
+            "bis" => Ok(Self::Bis),
+            "xor" => Ok(Self::Xor),
+            "and" => Ok(Self::And),
+            _ => Err("the provided mnemonic is not a two operand operation"),
+        }
+    }
+}
+
 impl From<TwoOp> for String {
     fn from(val: TwoOp) -> Self {
         match val {
@@ -123,6 +145,57 @@ pub enum EmulatedOp {
     Sbc,
 }
 
+impl FromStr for EmulatedOp {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ret" => Ok(Self::Ret),
+            "clrc" => Ok(Self::Clrc),
+            "setc" => Ok(Self::Setc),
+            "clrz" => Ok(Self::Clrz),
+            "setz" => Ok(Self::Setz),
+            "clrn" => Ok(Self::Clrn),
+            "setn" => Ok(Self::Setn),
+            "dint" => Ok(Self::Dint),
+            "eint" => Ok(Self::Eint),
+            "nop" => Ok(Self::Nop),
+            "br" => Ok(Self::Br),
+            "pop" => Ok(Self::Pop),
+            "rla" => Ok(Self::Rla),
+            "rlc" => Ok(Self::Rlc),
+            "inv" => Ok(Self::Inv),
+            "clr" => Ok(Self::Clr),
+            "tst" => Ok(Self::Tst),
+            "dec" => Ok(Self::Dec),
+            "decd" => Ok(Self::Decd),
+            "inc" => Ok(Self::Inc),
+            "incd" => Ok(Self::Incd),
+            "adc" => Ok(Self::Adc),
+            "dadc" => Ok(Self::Dadc),
+            "sbc" => Ok(Self::Sbc),
+            _ => Err("the provided mnemonic is not an emulated operation"),
+        }
+    }
+}
+
+impl EmulatedOp {
+    pub fn takes_operand(&self) -> bool {
+        !matches!(
+            self,
+            Self::Ret
+                | Self::Clrc
+                | Self::Setc
+                | Self::Clrz
+                | Self::Setz
+                | Self::Clrn
+                | Self::Setn
+                | Self::Dint
+                | Self::Eint
+                | Self::Nop
+        )
+    }
+}
+
 impl From<EmulatedOp> for String {
     fn from(val: EmulatedOp) -> Self {
         match val {
@@ -161,51 +234,132 @@ impl fmt::Display for EmulatedOp {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TwoOpInstruction {
     operation: TwoOp,
     source: AddresingMode,
     destination: AddresingMode,
     mode: DataMode,
+    // Whether an immediate source may come from the constant generator registers
+    constant_generator: bool,
 }
 
-impl AsmInstruction for TwoOpInstruction {}
+impl AsmInstruction for TwoOpInstruction {
+    fn encode(&self) -> Vec<u16> {
+        let (src_register, src_addressing_bits, src_word) =
+            encode_source(self.source, self.constant_generator);
+        // Destinations are validated when the instruction is built
+        let (dst_register, dst_addressing_bit, dst_word) =
+            encode_destination(self.destination).unwrap();
+
+        let op_word = u16::from(self.operation)
+            + u16::from(src_register).shl(8)
+            + dst_addressing_bit.shl(7)
+            + u16::from(self.mode)
+            + src_addressing_bits.shl(4)
+            + u16::from(dst_register);
+
+        [Some(op_word), src_word, dst_word]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
 
 impl TwoOpInstruction {
-    pub fn new(raw_words: &[u16]) -> (Self, u8) {
-        let operation = TwoOp::try_from(raw_words[0]).unwrap();
-        let (source, destination, extra_words) = Self::parse_src_dst(raw_words);
-        let mode = DataMode::from(raw_words[0]);
+    pub fn build(
+        operation: TwoOp,
+        source: AddresingMode,
+        destination: AddresingMode,
+        mode: DataMode,
+    ) -> Result<Self, &'static str> {
+        encode_destination(destination)?;
+        Ok(Self {
+            operation,
+            source,
+            destination,
+            mode,
+            constant_generator: true,
+        })
+    }
+
+    // Disabling the constant generator forces immediates into the long `@PC+` form
+    pub fn with_constant_generator(mut self, enabled: bool) -> Self {
+        self.constant_generator = enabled;
+        self
+    }
 
-        (
+    // Addresses of the source and destination extension words, for an instruction at `address`
+    pub fn extension_addresses(&self, address: u16) -> (Option<u16>, Option<u16>) {
+        let source_address = encode_source(self.source, self.constant_generator)
+            .2
+            .map(|_| address.wrapping_add(2));
+        let destination_address = encode_destination(self.destination)
+            .ok()
+            .and_then(|(_, _, word)| word)
+            .map(|_| address.wrapping_add(if source_address.is_some() { 4 } else { 2 }));
+
+        (source_address, destination_address)
+    }
+
+    pub fn operation(&self) -> TwoOp {
+        self.operation
+    }
+
+    pub fn source(&self) -> AddresingMode {
+        self.source
+    }
+
+    pub fn destination(&self) -> AddresingMode {
+        self.destination
+    }
+
+    pub fn mode(&self) -> DataMode {
+        self.mode
+    }
+
+    pub fn new(raw_words: &[u16]) -> Result<(Self, u8), DecodeError> {
+        let first_word = *raw_words.first().ok_or(DecodeError::EmptyInput)?;
+        let operation = TwoOp::try_from(first_word)?;
+        let (source, destination, extra_words, constant_generator) =
+            Self::parse_src_dst(raw_words)?;
+        let mode = DataMode::from(first_word);
+
+        Ok((
             Self {
                 operation,
                 source,
                 destination,
                 mode,
+                constant_generator,
             },
             extra_words,
-        )
+        ))
     }
 
-    fn parse_src_dst(raw_words: &[u16]) -> (AddresingMode, AddresingMode, u8) {
+    fn parse_src_dst(
+        raw_words: &[u16],
+    ) -> Result<(AddresingMode, AddresingMode, u8, bool), DecodeError> {
         let src_addressing_bits = raw_words[0].shr(4) & 0b11u16;
         let src_register_bits = raw_words[0].shr(8) & 0b1111u16;
-        let src_register = Register::try_from(src_register_bits).unwrap();
+        let src_register =
+            Register::try_from(src_register_bits).expect("register identifiers are 4 bits wide");
 
         let (src_mode, first_extra_word_used) =
-            parse_address(src_register, src_addressing_bits, raw_words).unwrap();
+            parse_address(src_register, src_addressing_bits, raw_words)?;
 
         let mut extra_words_used = if first_extra_word_used { 1u8 } else { 0u8 };
+        let constant_generator = uses_constant_generator(src_mode, first_extra_word_used);
 
         let dst_addressing_bit = raw_words[0] & 0b10000000u16;
         let dst_register_bits = raw_words[0] & 0b1111u16;
-        let dst_register = Register::try_from(dst_register_bits).unwrap();
+        let dst_register =
+            Register::try_from(dst_register_bits).expect("register identifiers are 4 bits wide");
 
         let dst_mode = if dst_addressing_bit == 0 {
             AddresingMode::Direct(dst_register)
         } else {
-            let offset = raw_words[1 + usize::from(extra_words_used)];
+            let offset = extension_word(raw_words, 1 + usize::from(extra_words_used))?;
             extra_words_used += 1;
             match dst_register {
                 Register::Pc => AddresingMode::Symbolic(offset),
@@ -214,13 +368,16 @@ impl TwoOpInstruction {
             }
         };
 
-        (src_mode, dst_mode, extra_words_used)
+        Ok((src_mode, dst_mode, extra_words_used, constant_generator))
     }
 
     pub fn emulated_form(&self) -> Option<EmulatedInstruction> {
         match self.operation {
             TwoOp::Mov => {
-                if self.source == self.destination {
+                // Only `mov #0, r3` has no effect, other moves may access memory or registers
+                if self.source == AddresingMode::Immediate(0)
+                    && self.destination == AddresingMode::Direct(Register::Cg)
+                {
                     return Some(EmulatedInstruction {
                         operation: EmulatedOp::Nop,
                         data: None,
@@ -241,7 +398,7 @@ impl TwoOpInstruction {
                             mode: Some(self.mode),
                         });
                     }
-                } else if self.source == AddresingMode::Absolute(0) {
+                } else if self.source == AddresingMode::Immediate(0) {
                     return Some(EmulatedInstruction {
                         operation: EmulatedOp::Clr,
                         data: Some(self.destination),
@@ -318,10 +475,10 @@ impl TwoOpInstruction {
                 }
             }
             TwoOp::Add => {
-                if self.source == self.destination {
+                if is_repeated_operand(self.source, self.destination) {
                     return Some(EmulatedInstruction {
                         operation: EmulatedOp::Rla,
-                        data: Some(self.destination),
+                        data: Some(self.source),
                         mode: Some(self.mode),
                     });
                 }
@@ -342,10 +499,10 @@ impl TwoOpInstruction {
                 }
             }
             TwoOp::Addc => {
-                if self.source == self.destination {
+                if is_repeated_operand(self.source, self.destination) {
                     return Some(EmulatedInstruction {
                         operation: EmulatedOp::Rlc,
-                        data: Some(self.destination),
+                        data: Some(self.source),
                         mode: Some(self.mode),
                     });
                 }
@@ -416,40 +573,270 @@ impl TwoOpInstruction {
     }
 }
 
-impl fmt::Display for TwoOpInstruction {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+// Symbolic offsets are relative to their own extension word, so the same location used twice
+// shows up with a destination offset two bytes smaller than the source one
+fn is_repeated_operand(source: AddresingMode, destination: AddresingMode) -> bool {
+    match (source, destination) {
+        (AddresingMode::Symbolic(src_offset), AddresingMode::Symbolic(dst_offset)) => {
+            dst_offset == src_offset.wrapping_sub(2)
+        }
+        _ => source == destination,
+    }
+}
+
+impl TwoOpInstruction {
+    pub fn to_string_with(&self, registers: &RegisterMap) -> String {
         let mode_string = if self.mode == DataMode::Byte {
             ".b"
         } else {
             ""
         };
-        write!(
-            f,
+        format!(
             "{}{} {} {}",
-            self.operation, mode_string, self.source, self.destination
+            self.operation,
+            mode_string,
+            self.source.named(registers),
+            self.destination.named(registers)
         )
     }
 }
 
-#[derive(Debug)]
+impl fmt::Display for TwoOpInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_string_with(&RegisterMap::default()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct EmulatedInstruction {
     operation: EmulatedOp,
     data: Option<AddresingMode>,
     mode: Option<DataMode>,
 }
 
-impl fmt::Display for EmulatedInstruction {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl EmulatedInstruction {
+    pub fn build(
+        operation: EmulatedOp,
+        data: Option<AddresingMode>,
+        mode: Option<DataMode>,
+    ) -> Result<Self, &'static str> {
+        if !operation.takes_operand() {
+            if data.is_some() {
+                return Err("the operation does not take operands");
+            }
+            if mode == Some(DataMode::Byte) {
+                return Err("the operation does not support byte mode");
+            }
+            return Ok(Self {
+                operation,
+                data: None,
+                mode: None,
+            });
+        }
+
+        let operand = data.ok_or("the operation requires an operand")?;
+        if operation == EmulatedOp::Br {
+            if mode == Some(DataMode::Byte) {
+                return Err("the operation does not support byte mode");
+            }
+            return Ok(Self {
+                operation,
+                data,
+                mode: None,
+            });
+        }
+
+        encode_destination(operand)?;
+        Ok(Self {
+            operation,
+            data,
+            mode: Some(mode.unwrap_or(DataMode::Word)),
+        })
+    }
+
+    pub fn operation(&self) -> EmulatedOp {
+        self.operation
+    }
+
+    pub fn data(&self) -> Option<AddresingMode> {
+        self.data
+    }
+
+    pub fn mode(&self) -> Option<DataMode> {
+        self.mode
+    }
+}
+
+impl From<EmulatedInstruction> for TwoOpInstruction {
+    fn from(val: EmulatedInstruction) -> Self {
+        let status = AddresingMode::Direct(Register::Sr);
+        let data = val.data.unwrap_or(AddresingMode::Direct(Register::Cg));
+        let (operation, source, destination) = match val.operation {
+            EmulatedOp::Ret => (
+                TwoOp::Mov,
+                AddresingMode::Autoincrement(Register::Sp),
+                AddresingMode::Direct(Register::Pc),
+            ),
+            EmulatedOp::Clrc => (TwoOp::Bic, AddresingMode::Immediate(1), status),
+            EmulatedOp::Setc => (TwoOp::Bis, AddresingMode::Immediate(1), status),
+            EmulatedOp::Clrz => (TwoOp::Bic, AddresingMode::Immediate(2), status),
+            EmulatedOp::Setz => (TwoOp::Bis, AddresingMode::Immediate(2), status),
+            EmulatedOp::Clrn => (TwoOp::Bic, AddresingMode::Immediate(4), status),
+            EmulatedOp::Setn => (TwoOp::Bis, AddresingMode::Immediate(4), status),
+            EmulatedOp::Dint => (TwoOp::Bic, AddresingMode::Immediate(8), status),
+            EmulatedOp::Eint => (TwoOp::Bis, AddresingMode::Immediate(8), status),
+            EmulatedOp::Nop => (
+                TwoOp::Mov,
+                AddresingMode::Immediate(0),
+                AddresingMode::Direct(Register::Cg),
+            ),
+            EmulatedOp::Br => (TwoOp::Mov, data, AddresingMode::Direct(Register::Pc)),
+            EmulatedOp::Pop => (TwoOp::Mov, AddresingMode::Autoincrement(Register::Sp), data),
+            EmulatedOp::Rla => (TwoOp::Add, data, repeat_operand(data)),
+            EmulatedOp::Rlc => (TwoOp::Addc, data, repeat_operand(data)),
+            EmulatedOp::Inv => (TwoOp::Xor, AddresingMode::Immediate(0xffff), data),
+            EmulatedOp::Clr => (TwoOp::Mov, AddresingMode::Immediate(0), data),
+            EmulatedOp::Tst => (TwoOp::Cmp, AddresingMode::Immediate(0), data),
+            EmulatedOp::Dec => (TwoOp::Sub, AddresingMode::Immediate(1), data),
+            EmulatedOp::Decd => (TwoOp::Sub, AddresingMode::Immediate(2), data),
+            EmulatedOp::Inc => (TwoOp::Add, AddresingMode::Immediate(1), data),
+            EmulatedOp::Incd => (TwoOp::Add, AddresingMode::Immediate(2), data),
+            EmulatedOp::Adc => (TwoOp::Addc, AddresingMode::Immediate(0), data),
+            EmulatedOp::Dadc => (TwoOp::Dadd, AddresingMode::Immediate(0), data),
+            EmulatedOp::Sbc => (TwoOp::Subc, AddresingMode::Immediate(0), data),
+        };
+
+        Self {
+            operation,
+            source,
+            destination,
+            mode: val.mode.unwrap_or(DataMode::Word),
+            // Emulated instructions are defined on top of the constant generator
+            constant_generator: true,
+        }
+    }
+}
+
+// The destination extension word follows the source one, moving symbolic offsets back by two
+fn repeat_operand(operand: AddresingMode) -> AddresingMode {
+    if let AddresingMode::Symbolic(offset) = operand {
+        AddresingMode::Symbolic(offset.wrapping_sub(2))
+    } else {
+        operand
+    }
+}
+
+impl EmulatedInstruction {
+    pub fn to_string_with(&self, registers: &RegisterMap) -> String {
         let mode_string = if self.mode == Some(DataMode::Byte) {
             ".b"
         } else {
             ""
         };
-        let data_string = if let Some(actual_data) = self.data {
-            format!("{}", actual_data)
+        if let Some(actual_data) = self.data {
+            format!(
+                "{}{} {}",
+                self.operation,
+                mode_string,
+                actual_data.named(registers)
+            )
         } else {
-            "".to_string()
-        };
-        write!(f, "{}{} {}", self.operation, mode_string, data_string)
+            format!("{}{}", self.operation, mode_string)
+        }
+    }
+}
+
+impl fmt::Display for EmulatedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_string_with(&RegisterMap::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emulated(raw_words: &[u16]) -> Option<EmulatedOp> {
+        let (instruction, _) = TwoOpInstruction::new(raw_words).unwrap();
+        instruction
+            .emulated_form()
+            .map(|emulated| emulated.operation())
+    }
+
+    #[test]
+    fn emulated_forms_are_recognised() {
+        let cases = [
+            (&[0x4130][..], EmulatedOp::Ret),
+            (&[0x4303], EmulatedOp::Nop),
+            (&[0x4135], EmulatedOp::Pop),
+            (&[0x4030, 0x1234], EmulatedOp::Br),
+            (&[0x4305], EmulatedOp::Clr),
+            (&[0x5315], EmulatedOp::Inc),
+            (&[0x8325], EmulatedOp::Decd),
+            (&[0x9305], EmulatedOp::Tst),
+            (&[0x5505], EmulatedOp::Rla),
+            (&[0x6505], EmulatedOp::Rlc),
+            (&[0xe335], EmulatedOp::Inv),
+            (&[0x6305], EmulatedOp::Adc),
+            (&[0xc232], EmulatedOp::Dint),
+            (&[0xd312], EmulatedOp::Setc),
+        ];
+        for (raw_words, operation) in cases {
+            assert_eq!(emulated(raw_words), Some(operation), "{:04x?}", raw_words);
+        }
+    }
+
+    #[test]
+    fn writes_to_the_constant_generator_keep_their_operands() {
+        assert_eq!(emulated(&[0x4533]), None);
+        assert_eq!(emulated(&[0x4033, 0x1234]), None);
+        assert_eq!(emulated(&[0x4213, 0x0200]), None);
+    }
+
+    #[test]
+    fn moves_between_identical_operands_are_not_nop() {
+        assert_eq!(emulated(&[0x4505]), None);
+        assert_eq!(emulated(&[0x4092, 0x0010, 0x0010]), None);
+        assert_eq!(emulated(&[0x4292, 0x0200, 0x0200]), None);
+    }
+
+    #[test]
+    fn plain_instructions_have_no_emulated_form() {
+        assert_eq!(emulated(&[0x4506]), None);
+        assert_eq!(emulated(&[0x5506]), None);
+    }
+
+    #[test]
+    fn built_instructions_decode_to_themselves() {
+        let immediate = AddresingMode::Immediate(1);
+        let register = AddresingMode::Direct(Register::R6);
+        let cases = [
+            TwoOpInstruction::build(
+                TwoOp::Mov,
+                AddresingMode::Direct(Register::R5),
+                register,
+                DataMode::Word,
+            ),
+            TwoOpInstruction::build(TwoOp::Add, immediate, register, DataMode::Word),
+            TwoOpInstruction::build(
+                TwoOp::Add,
+                AddresingMode::Immediate(5),
+                register,
+                DataMode::Word,
+            ),
+        ]
+        .map(Result::unwrap);
+        for instruction in cases
+            .into_iter()
+            .chain([cases[1].with_constant_generator(false)])
+        {
+            let (decoded, _) = TwoOpInstruction::new(&instruction.encode()).unwrap();
+            assert_eq!(decoded, instruction);
+        }
+    }
+
+    #[test]
+    fn empty_input_is_an_error() {
+        assert_eq!(TwoOpInstruction::new(&[]), Err(DecodeError::EmptyInput));
     }
 }